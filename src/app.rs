@@ -1,13 +1,33 @@
+use std::collections::{ HashSet, VecDeque };
 use std::error::Error;
-use std::process::{ Child, Command };
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::process::Command;
+use std::sync::atomic::{ AtomicBool, Ordering };
 use std::sync::{ Arc, Mutex };
-use crate::search::{ search_youtube, search_archive };
+use std::thread;
+use std::time::Instant;
+use crate::cache::{ AudioCache, CacheEntry };
+use crate::config::Config;
+use crate::daemon::{ DaemonClient, DaemonError, DaemonStatus };
+use crate::download::{ download_playlist, download_track, keep_file, outcome_message, DownloadOptions };
+use crate::history::DownloadHistory;
+use crate::playlists::PlaylistStore;
+use crate::library::{ watch_library, BrowseSort, Facet, Library };
+use notify::RecommendedWatcher;
+use serde_json::{ json, Value };
+use crate::network::spawn_connectivity_check;
+use crate::plugins::discover_plugins;
+use crate::podcasts::{ fetch_feed, PodcastStore };
+use crate::positions::PlaybackPositions;
+use crate::replaygain::{ spawn_analysis, GainCache, GainMode };
+use crate::radio::RadioFavorites;
+use crate::recorder::StreamRecorder;
+use crate::sources::{ MusicSource, SourceRegistry, Stream, LOCAL, PODCAST, RADIO };
+use crate::stream::{ start_decoder, step_speed, Playback, PlaybackEffects, PlaybackReport };
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum Source {
-    YouTube,
-    InternetArchive,
-}
+// How long before the playing track ends the next one starts decoding, in seconds
+const PREFETCH_LEAD: f64 = 20.0;
 
 #[derive(PartialEq)]
 pub enum Mode {
@@ -15,93 +35,1337 @@ pub enum Mode {
     Download,
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum View {
     SearchInput,
     SearchResults,
     InitialSelection,
     SourceSelection,
     Streaming,
+    DownloadOptions,
+    DownloadExists,
     Downloading,
+    Library,
+    Playlists,
+    PlaylistTracks,
+    Podcasts,
+    PodcastEpisodes,
+    Cache,
+    Resume,
+    Prompt,
+}
+
+/// What the library browser lists at its current position
+pub enum BrowseEntries {
+    Facets,
+    Values(Facet, Vec<(String, usize)>),
+    Tracks(Vec<SearchResult>),
 }
 
 #[derive(Debug, Clone)]
 pub struct SearchResult {
     pub identifier: String,
     pub title: String,
-    pub source: Source,
+    pub artist: Option<String>,
+    pub year: Option<String>,
+    // Length in seconds, when the source reports it
+    pub duration: Option<u64>,
+    // Key of the source in the `SourceRegistry`
+    pub source: String,
+}
+
+impl SearchResult {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "source": self.source,
+            "identifier": self.identifier,
+            "title": self.title,
+            "artist": self.artist,
+            "year": self.year,
+            "duration": self.duration,
+        })
+    }
+
+    /// Every word of the query appears in the title or artist
+    pub fn matches(&self, query: &str) -> bool {
+        let text = format!("{} {}", self.title, self.artist.as_deref().unwrap_or("")).to_lowercase();
+        query
+            .split_whitespace()
+            .all(|word| text.contains(&word.to_lowercase()))
+    }
+
+    pub fn from_json(item: &Value) -> Option<SearchResult> {
+        Some(SearchResult {
+            identifier: item["identifier"].as_str()?.to_string(),
+            title: item["title"].as_str()?.to_string(),
+            artist: item["artist"].as_str().map(String::from),
+            year: item["year"].as_str().map(String::from),
+            duration: item["duration"].as_u64(),
+            source: item["source"].as_str()?.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PromptKind {
+    PlaylistName,
+    ImportPath,
+    ExportPath,
+    FeedUrl,
+}
+
+impl PromptKind {
+    pub fn title(&self) -> &'static str {
+        match self {
+            PromptKind::PlaylistName => "New Playlist Name",
+            PromptKind::ImportPath => "Import Playlist (path to .m3u, .m3u8 or .xspf)",
+            PromptKind::ExportPath => "Export Playlist (.m3u8 or .xspf path)",
+            PromptKind::FeedUrl => "Subscribe to Podcast (RSS or Atom feed URL)",
+        }
+    }
+}
+
+// A track whose stream a worker thread is looking up. Sources may take a
+// while (yt-dlp, a slow plugin), and the interface keeps going meanwhile.
+struct Resolving {
+    track: SearchResult,
+    result: Arc<Mutex<Option<Result<Stream, String>>>>,
+}
+
+impl Resolving {
+    fn take_result(&self) -> Option<Result<Stream, String>> {
+        self.result.lock().unwrap().take()
+    }
+}
+
+// A track that starts playing once its stream is resolved
+struct Launching {
+    resolving: Resolving,
+    start: u64,
+    effects: PlaybackEffects,
+}
+
+// The front of the queue, already decoding behind the playing track
+struct Prefetched {
+    track: SearchResult,
+    effects: PlaybackEffects,
+    report: Arc<Mutex<PlaybackReport>>,
+}
+
+/// A one-line text question shown in `View::Prompt`
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub input: String,
+    // Where Enter or ← leads once the prompt is done
+    pub return_view: View,
 }
 
 pub struct AppUi {
     pub search_input: String,
     pub search_results: Vec<SearchResult>,
+    // Set when the results are the entries of a YouTube playlist or channel
+    pub playlist_title: Option<String>,
+    pub marked_results: HashSet<usize>,
     pub selected_result_index: Option<usize>,
     pub selected_source_index: usize,
-    pub source: Source,
+    pub sources: SourceRegistry,
+    pub source: Arc<dyn MusicSource>,
     pub current_view: View,
     pub visualization_data: Arc<Mutex<Vec<u8>>>,
-    pub ffplay_process: Option<Child>,
+    pub playback: Option<Playback>,
+    // Switches to a prefetched track seen so far, and whether the front
+    // of the queue was already considered for prefetching
+    advances: usize,
+    prefetched: Option<Prefetched>,
+    prefetching: Option<Resolving>,
+    prefetch_tried: bool,
+    launching: Option<Launching>,
     pub mode: Option<Mode>,
     pub current_equalizer: usize,
     pub download_status: Arc<Mutex<Option<String>>>,
+    pub download_options: DownloadOptions,
+    pub config: Config,
+    pub history: Arc<Mutex<DownloadHistory>>,
+    // Offline mode as chosen with O, and whether the network is there at all
+    pub offline: bool,
+    online: Arc<AtomicBool>,
+    pub library: Arc<Mutex<Library>>,
+    // Kept alive so the library folders stay watched, see `start_workers`
+    _library_watcher: Option<RecommendedWatcher>,
+    workers_started: bool,
+    pub selected_option_index: usize,
     pub paused: bool,
+    pub now_playing: Option<SearchResult>,
+    // Seconds played up to the last pause or seek, and since when it has
+    // been playing again. Together they give the position in the track.
+    played_before: u64,
+    playing_since: Option<Instant>,
+    // Live streams have no position to come back to, and play at normal speed
+    resumable: bool,
+    // Speed of the playing track, and the spoken-word filters, which stay
+    // as they are from track to track
+    pub effects: PlaybackEffects,
+    pub playback_report: Arc<Mutex<PlaybackReport>>,
+    pub gain_mode: GainMode,
+    // Loudness analysis of local files, filled in the background
+    pub gains: Arc<Mutex<GainCache>>,
+    pub positions: PlaybackPositions,
+    pub cache: AudioCache,
+    // The cache view's entries, read when it opens
+    pub cache_entries: Vec<CacheEntry>,
+    pub cache_index: usize,
+    // Queued track last handed to the cache to download
+    cache_requested: Option<SearchResult>,
+    // Track waiting for the answer to "resume from ...?", with its saved position
+    pub pending_resume: Option<(SearchResult, u64)>,
+    // Song announced by the radio station that is playing
+    pub stream_title: Arc<Mutex<Option<String>>>,
+    pub recorder: Arc<Mutex<StreamRecorder>>,
+    // How saving the playing track went, shown as a notice
+    keep_status: Arc<Mutex<Option<String>>>,
+    pub radio_favorites: Arc<Mutex<RadioFavorites>>,
+    pub podcasts: Arc<Mutex<PodcastStore>>,
+    pub podcast_index: usize,
+    // Feed U was pressed on once, a second U unsubscribes
+    pub unsubscribe_pending: Option<String>,
+    pub episode_index: usize,
+    pub queue: VecDeque<SearchResult>,
+    // Where ← leads from the streaming view
+    pub back_view: View,
+    // Where ← leads from the downloads view
+    pub download_back_view: View,
+    // Library browser: values picked so far, facet being listed (None lists
+    // tracks, or the facet menu when nothing is picked yet)
+    pub browse_filters: Vec<(Facet, String)>,
+    pub browse_facet: Option<Facet>,
+    pub browse_sort: BrowseSort,
+    pub browse_index: usize,
+    pub playlists: PlaylistStore,
+    pub playlist_index: usize,
+    pub playlist_track_index: usize,
+    // Playlist that "+" adds to, the last one opened
+    pub active_playlist: Option<usize>,
+    pub prompt: Option<Prompt>,
+    // One-off message shown under the header until the next key press
+    pub notice: Option<String>,
+    // Set when playback is left to a daemon. Its queue and recording as of
+    // the last status.
+    pub daemon: Option<DaemonClient>,
+    daemon_queue: Vec<SearchResult>,
+    daemon_recording: bool,
 }
 
 impl AppUi {
     pub fn new() -> Self {
+        let config = Config::load();
+        let library = Arc::new(Mutex::new(Library::load()));
+        let gains = Arc::new(Mutex::new(GainCache::load()));
+        let history = Arc::new(Mutex::new(DownloadHistory::load()));
+        let cache = AudioCache::new(&config);
+        let recorder = Arc::new(Mutex::new(StreamRecorder::new("", &config)));
+        let radio_favorites = Arc::new(Mutex::new(RadioFavorites::load()));
+        let podcasts = Arc::new(Mutex::new(PodcastStore::load()));
+        let mut sources = SourceRegistry::new(
+            &config,
+            Arc::clone(&library),
+            Arc::clone(&radio_favorites),
+            Arc::clone(&podcasts),
+            Arc::clone(&history),
+            cache.clone()
+        );
+        for plugin in discover_plugins(&config.plugin_dir) {
+            sources.register(Arc::new(plugin));
+        }
+        let offline = config.offline;
+        let online = Arc::new(AtomicBool::new(true));
+        spawn_connectivity_check(Arc::clone(&online));
+        let gain_mode = config.replaygain;
+        let effects = PlaybackEffects {
+            smart_speed: config.smart_speed,
+            normalize: config.normalize_loudness,
+            ..PlaybackEffects::default()
+        };
         AppUi {
             search_input: String::new(),
             search_results: Vec::new(),
+            playlist_title: None,
+            marked_results: HashSet::new(),
             selected_result_index: Some(0),
             selected_source_index: 0,
-            source: Source::YouTube,
+            source: Arc::clone(&sources.all()[0]),
+            sources,
             current_view: View::SearchInput,
             visualization_data: Arc::new(Mutex::new(vec![0; 10])),
-            ffplay_process: None,
+            playback: None,
+            advances: 0,
+            prefetched: None,
+            prefetching: None,
+            prefetch_tried: false,
+            launching: None,
             current_equalizer: 0,
             mode: None,
             download_status: Arc::new(Mutex::new(None)),
+            download_options: config.download_options,
+            config,
+            history,
+            offline,
+            online,
+            library,
+            _library_watcher: None,
+            workers_started: false,
+            selected_option_index: 0,
             paused: false,
+            now_playing: None,
+            played_before: 0,
+            playing_since: None,
+            resumable: false,
+            effects,
+            playback_report: Arc::new(Mutex::new(PlaybackReport::default())),
+            gain_mode,
+            gains,
+            positions: PlaybackPositions::load(),
+            cache,
+            cache_entries: Vec::new(),
+            cache_index: 0,
+            cache_requested: None,
+            pending_resume: None,
+            stream_title: Arc::new(Mutex::new(None)),
+            recorder,
+            keep_status: Arc::new(Mutex::new(None)),
+            radio_favorites,
+            podcasts,
+            podcast_index: 0,
+            unsubscribe_pending: None,
+            episode_index: 0,
+            queue: VecDeque::new(),
+            back_view: View::SearchResults,
+            download_back_view: View::SearchResults,
+            browse_filters: Vec::new(),
+            browse_facet: None,
+            browse_sort: BrowseSort::Name,
+            browse_index: 0,
+            playlists: PlaylistStore::load(),
+            playlist_index: 0,
+            playlist_track_index: 0,
+            active_playlist: None,
+            prompt: None,
+            notice: None,
+            daemon: None,
+            daemon_queue: Vec::new(),
+            daemon_recording: false,
+        }
+    }
+
+    /// Starts keeping the library index and the loudness analysis up to
+    /// date. Only one process does that: the daemon, or the window playing
+    /// without one.
+    pub fn start_workers(&mut self) {
+        if self.workers_started {
+            return;
+        }
+        self.workers_started = true;
+        let dirs = self.config.library_dirs.clone();
+        // Downloads are watched from the first one on, before the folder exists
+        if dirs.contains(&self.config.download_dir) {
+            let _ = fs::create_dir_all(&self.config.download_dir);
+        }
+        let (watcher, unwatched) = watch_library(&dirs, Arc::clone(&self.library));
+        self._library_watcher = watcher;
+        if let Some((dir, e)) = unwatched.first() {
+            self.notice = Some(format!("Not watching {} for new files: {}", dir.display(), e));
+        }
+        spawn_library_scan(dirs, Arc::clone(&self.library));
+        let downloads = self.history.lock().unwrap().paths();
+        spawn_analysis(Arc::clone(&self.library), downloads, Arc::clone(&self.gains), self.config.replaygain_tags);
+    }
+
+    /// A window attached to a daemon reads the index the daemon keeps
+    pub fn reload_library(&self) {
+        if self.daemon.is_some() {
+            *self.library.lock().unwrap() = Library::load();
         }
     }
 
+    // A daemon and the windows attached to it each hold a copy of the
+    // playlists, radio favorites, podcasts and saved positions. Every change
+    // starts from what is on disk, so no process saves over what another
+    // one saved meanwhile.
+
+    pub fn reload_playlists(&mut self) {
+        self.playlists = PlaylistStore::load();
+    }
+
+    pub fn reload_podcasts(&self) {
+        *self.podcasts.lock().unwrap() = PodcastStore::load();
+    }
+
+    fn reload_radio_favorites(&self) {
+        *self.radio_favorites.lock().unwrap() = RadioFavorites::load();
+    }
+
+    pub fn reload_positions(&mut self) {
+        self.positions = PlaybackPositions::load();
+    }
+
     pub async fn search(&mut self) -> Result<(), Box<dyn Error>> {
-        self.search_results = match self.source {
-            Source::YouTube => search_youtube(&self.search_input).await?,
-            Source::InternetArchive => search_archive(&self.search_input).await?,
-        };
+        self.reload_library();
+        self.playlist_title = None;
+        self.marked_results.clear();
+        // Sources block on yt-dlp and HTTP requests, keep that off the runtime
+        let source = Arc::clone(&self.source);
+        let query = self.search_input.clone();
+        let (title, results) = tokio::task
+            ::spawn_blocking(move || {
+                if source.is_collection(&query) {
+                    source.list_tracks(&query).map(|(title, entries)| (Some(title), entries))
+                } else {
+                    source.search(&query).map(|results| (None, results))
+                }
+            }).await?
+            .map_err(|e| e as Box<dyn Error>)?;
+        self.playlist_title = title;
+        self.search_results = results;
         self.current_view = View::SearchResults;
         self.selected_result_index = Some(0);
         Ok(())
     }
 
+    /// Sources offered in `View::SourceSelection`
+    pub fn available_sources(&self) -> Vec<Arc<dyn MusicSource>> {
+        self.sources
+            .all()
+            .iter()
+            .filter(|source| self.mode != Some(Mode::Download) || source.can_download())
+            .cloned()
+            .collect()
+    }
+
+    pub fn is_downloaded(&self, result: &SearchResult) -> bool {
+        self.history.lock().unwrap().find(&result.source, &result.identifier).is_some()
+    }
+
+    pub fn toggle_mark(&mut self, index: usize) {
+        if !self.marked_results.remove(&index) {
+            self.marked_results.insert(index);
+        }
+    }
+
+    pub fn toggle_mark_all(&mut self) {
+        if self.marked_results.len() == self.search_results.len() {
+            self.marked_results.clear();
+        } else {
+            self.marked_results = (0..self.search_results.len()).collect();
+        }
+    }
+
+    pub fn browse_entries(&self) -> BrowseEntries {
+        let library = self.library.lock().unwrap();
+        match self.browse_facet {
+            None if self.browse_filters.is_empty() => BrowseEntries::Facets,
+            Some(facet) =>
+                BrowseEntries::Values(
+                    facet,
+                    library.facet_values(facet, &self.browse_filters, self.browse_sort)
+                ),
+            None =>
+                BrowseEntries::Tracks(
+                    library
+                        .filtered_tracks(&self.browse_filters, self.browse_sort)
+                        .into_iter()
+                        .map(|track| track.to_search_result())
+                        .collect()
+                ),
+        }
+    }
+
+    /// Every track below `value` of `facet` at the current browse position, in album order
+    pub fn browse_tracks_under(&self, facet: Facet, value: &str) -> Vec<SearchResult> {
+        let mut filters = self.browse_filters.clone();
+        filters.push((facet, value.to_string()));
+        self.library
+            .lock()
+            .unwrap()
+            .filtered_tracks(&filters, BrowseSort::Tracks)
+            .into_iter()
+            .map(|track| track.to_search_result())
+            .collect()
+    }
+
+    pub fn browse_into(&mut self, facet: Facet, value: String) {
+        self.browse_filters.push((facet, value));
+        self.browse_facet = facet.drill_down();
+        self.browse_index = 0;
+    }
+
+    /// Goes one level up, back to the facet menu at the top
+    pub fn browse_back(&mut self) {
+        self.browse_facet = self.browse_filters.pop().map(|(facet, _)| facet);
+        self.browse_index = 0;
+    }
+
+    /// Adds tracks to the active playlist, creating one if there is none yet
+    pub fn add_to_playlist(&mut self, tracks: Vec<SearchResult>) {
+        if tracks.is_empty() {
+            return;
+        }
+        self.reload_playlists();
+        let index = match self.active_playlist.filter(|i| *i < self.playlists.playlists.len()) {
+            Some(index) => index,
+            None => self.playlists.create("My Playlist", Vec::new()),
+        };
+        self.active_playlist = Some(index);
+        let playlist = &mut self.playlists.playlists[index];
+        let count = tracks.len();
+        playlist.tracks.extend(tracks);
+        self.notice = Some(match self.playlists.save() {
+            Ok(_) => format!("Added {} track(s) to {}", count, self.playlists.playlists[index].name),
+            Err(e) => format!("Could not save playlists: {}", e),
+        });
+    }
+
+    /// Adds or removes a radio station from the favorites, other tracks are ignored
+    pub fn toggle_radio_favorite(&mut self, station: &SearchResult) {
+        if station.source != RADIO {
+            return;
+        }
+        self.reload_radio_favorites();
+        let mut favorites = self.radio_favorites.lock().unwrap();
+        let added = favorites.toggle(station);
+        self.notice = Some(match favorites.save() {
+            Ok(_) if added => format!("Added {} to radio favorites", station.title),
+            Ok(_) => format!("Removed {} from radio favorites", station.title),
+            Err(e) => format!("Could not save radio favorites: {}", e),
+        });
+    }
+
+    /// Fetches the feeds and stores what they list now, subscribing to the
+    /// ones that are new. Returns a summary that names the first feed that failed.
+    pub async fn refresh_podcasts(&mut self, feed_urls: Vec<String>) -> Result<String, Box<dyn Error>> {
+        if self.is_offline() {
+            return Ok("Offline, feeds are not fetched".to_string());
+        }
+        // Feeds are fetched with blocking requests, keep them off the runtime
+        let fetched = tokio::task
+            ::spawn_blocking(move || {
+                feed_urls
+                    .into_iter()
+                    .map(|url| {
+                        let podcast = fetch_feed(&url);
+                        (url, podcast)
+                    })
+                    .collect::<Vec<_>>()
+            }).await?;
+
+        self.reload_podcasts();
+        let mut podcasts = self.podcasts.lock().unwrap();
+        let mut updated = 0;
+        let mut failed = Vec::new();
+        for (url, podcast) in fetched {
+            match podcast {
+                Ok(podcast) => {
+                    podcasts.update(podcast);
+                    updated += 1;
+                }
+                Err(e) => failed.push(format!("{}: {}", url, e)),
+            }
+        }
+        podcasts.save()?;
+        Ok(match failed.first() {
+            Some(error) => format!("Updated {} feed(s), {} failed ({})", updated, failed.len(), error),
+            None => format!("Updated {} feed(s)", updated),
+        })
+    }
+
+    /// Marks an episode played or unplayed, other tracks are ignored
+    pub fn set_episode_played(&mut self, episode: &SearchResult, played: bool) {
+        if episode.source != PODCAST {
+            return;
+        }
+        self.reload_podcasts();
+        let mut podcasts = self.podcasts.lock().unwrap();
+        if podcasts.set_played(&episode.identifier, played) {
+            if let Err(e) = podcasts.save() {
+                self.notice = Some(format!("Could not save podcasts: {}", e));
+            }
+        }
+    }
+
+    pub fn open_prompt(&mut self, kind: PromptKind, input: String) {
+        self.prompt = Some(Prompt { kind, input, return_view: self.current_view });
+        self.current_view = View::Prompt;
+    }
+
+    /// Stops whatever is playing and starts `result`. A track that was
+    /// stopped halfway first asks whether to resume (`View::Resume`).
+    pub fn play(&mut self, result: SearchResult) -> Result<(), Box<dyn Error>> {
+        self.stop_streaming();
+        self.reload_positions();
+        if let Some(position) = self.positions.get(&result) {
+            self.pending_resume = Some((result, position));
+            self.selected_option_index = 0;
+            self.current_view = View::Resume;
+            return Ok(());
+        }
+        self.start_playback(result, 0)
+    }
+
+    /// Plays `result` from `start` seconds in, without asking
+    pub fn start_playback(&mut self, result: SearchResult, start: u64) -> Result<(), Box<dyn Error>> {
+        let effects = PlaybackEffects { speed: self.default_speed(&result), ..self.effects };
+        self.launch(result, start, effects)
+    }
+
+    /// The feed's speed for podcast episodes, else the one configured for the source
+    fn default_speed(&self, track: &SearchResult) -> f64 {
+        let feed_speed = if track.source == PODCAST {
+            self.podcasts.lock().unwrap().speed_for(&track.identifier)
+        } else {
+            None
+        };
+        feed_speed
+            .or_else(|| self.config.playback_speed.get(&track.source).copied())
+            .unwrap_or(1.0)
+    }
+
+    /// Steps the speed of the playing track up or down
+    pub fn change_speed(&mut self, faster: bool) -> Result<(), Box<dyn Error>> {
+        if !self.resumable {
+            self.notice = Some("Live streams play at normal speed".to_string());
+            return Ok(());
+        }
+        let speed = step_speed(self.effects.speed, faster);
+        self.apply_effects(PlaybackEffects { speed, ..self.effects })
+    }
+
+    pub fn toggle_smart_speed(&mut self) -> Result<(), Box<dyn Error>> {
+        let smart_speed = !self.effects.smart_speed;
+        self.notice = Some(
+            if !self.resumable && smart_speed {
+                "Smart speed on, from the next track (live streams keep their silences)".to_string()
+            } else {
+                format!("Smart speed {}", if smart_speed { "on" } else { "off" })
+            }
+        );
+        self.apply_effects(PlaybackEffects { smart_speed, ..self.effects })
+    }
+
+    /// Switches between track gain, album gain and no ReplayGain
+    pub fn cycle_gain_mode(&mut self) -> Result<(), Box<dyn Error>> {
+        self.gain_mode = self.gain_mode.next();
+        self.notice = Some(format!("ReplayGain: {}", self.gain_mode.label()));
+        self.apply_effects(self.effects)
+    }
+
+    // Only local files are analyzed
+    fn replay_gain(&self, track: &SearchResult) -> Option<f64> {
+        if track.source != LOCAL {
+            return None;
+        }
+        let gains = self.gains.lock().unwrap();
+        gains.get(Path::new(&track.identifier))?.gain(self.gain_mode)
+    }
+
+    pub fn toggle_normalize(&mut self) -> Result<(), Box<dyn Error>> {
+        let normalize = !self.effects.normalize;
+        self.notice = Some(format!("Loudness normalization {}", if normalize { "on" } else { "off" }));
+        self.apply_effects(PlaybackEffects { normalize, ..self.effects })
+    }
+
+    // ffmpeg cannot change its filters while decoding, so the track restarts
+    // where it is
+    fn apply_effects(&mut self, effects: PlaybackEffects) -> Result<(), Box<dyn Error>> {
+        let playing = self.now_playing.clone().filter(|_| self.playback.is_some());
+        match playing {
+            Some(track) => {
+                let position = if self.resumable { self.position() } else { 0 };
+                self.launch(track, position, effects)
+            }
+            None => {
+                // A track still loading starts with them
+                if let Some(launching) = &mut self.launching {
+                    launching.effects = effects;
+                }
+                self.effects = effects;
+                Ok(())
+            }
+        }
+    }
+
+    fn launch(&mut self, result: SearchResult, start: u64, effects: PlaybackEffects) -> Result<(), Box<dyn Error>> {
+        if self.daemon.is_some() {
+            let request = json!({ "command": "play", "track": result.to_json(), "position": start });
+            if self.daemon_request(request).is_some() {
+                self.now_playing = Some(result);
+                self.played_before = start;
+                self.paused = false;
+                self.current_view = View::Streaming;
+            }
+            return Ok(());
+        }
+        self.stop_streaming();
+        let resolving = self.resolve_track(&result);
+        self.launching = Some(Launching { resolving, start, effects });
+        self.start_launching()?;
+        if self.launching.is_some() && self.notice.is_none() {
+            self.notice = Some(loading_notice(&result));
+        }
+        Ok(())
+    }
+
+    // Starts the track `launch` is waiting for, once its stream is resolved
+    fn start_launching(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(stream) = self.launching.as_ref().and_then(|launching| launching.resolving.take_result()) else {
+            return Ok(());
+        };
+        let Some(Launching { resolving: Resolving { track: result, .. }, start, effects }) = self.launching.take() else {
+            return Ok(());
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                self.notice = Some(e);
+                return Ok(());
+            }
+        };
+        // Played from the start, what yt-dlp fetches is kept for next time
+        let keep = if start == 0 && matches!(stream, Stream::Extract(_)) { self.cache.begin(&result) } else { None };
+        // A fresh title slot, the relay of the previous station may still be winding down
+        self.stream_title = Arc::new(Mutex::new(None));
+        self.recorder = Arc::new(Mutex::new(StreamRecorder::new(&result.title, &self.config)));
+        self.playback_report = Arc::new(Mutex::new(PlaybackReport::default()));
+        let live = matches!(stream, Stream::Live(_));
+        let speed = if live { 1.0 } else { effects.speed };
+        let effects = PlaybackEffects { gain: self.replay_gain(&result), ..effects };
+        let decoder = start_decoder(
+            &stream,
+            start,
+            PlaybackEffects { speed, smart_speed: effects.smart_speed && !live, ..effects },
+            Arc::clone(&self.stream_title),
+            Arc::clone(&self.recorder),
+            Arc::clone(&self.playback_report),
+            keep
+        )?;
+        // Holding back the crossfade would delay a live stream by as much
+        let crossfade = if live { 0.0 } else { self.config.crossfade };
+        self.playback = Some(Playback::start(decoder, crossfade, Arc::clone(&self.visualization_data))?);
+        self.advances = 0;
+        self.prefetching = None;
+        self.prefetch_tried = false;
+        self.resumable = !live;
+        self.effects = PlaybackEffects { speed, ..effects };
+        self.played_before = start;
+        self.playing_since = Some(Instant::now());
+        self.set_episode_played(&result, true);
+        if self.notice.as_ref() == Some(&loading_notice(&result)) {
+            self.notice = None;
+        }
+        self.now_playing = Some(result);
+        self.paused = false;
+        self.current_view = View::Streaming;
+        Ok(())
+    }
+
+    // Looks up the stream of `track` on a worker thread, the cached copy
+    // when there is one. A source that is gone or fails to resolve (a broken
+    // plugin, say) only costs this track, not the session.
+    fn resolve_track(&self, track: &SearchResult) -> Resolving {
+        let resolving = Resolving { track: track.clone(), result: Arc::new(Mutex::new(None)) };
+        if let Some(path) = self.cache.lookup(track) {
+            *resolving.result.lock().unwrap() = Some(Ok(Stream::Direct(path.to_string_lossy().to_string())));
+            return resolving;
+        }
+        let Some(source) = self.sources.get(&track.source) else {
+            *resolving.result.lock().unwrap() = Some(Err(format!("{} is not available", track.source)));
+            return resolving;
+        };
+        let track = track.clone();
+        let result = Arc::clone(&resolving.result);
+        let offline = self.offline;
+        let online = Arc::clone(&self.online);
+        thread::spawn(move || {
+            let resolved = source
+                .resolve(&track)
+                .map_err(|e| format!("Could not play {}: {}", track.title, e))
+                .and_then(|stream| {
+                    if stream.is_remote() && (offline || !online.load(Ordering::Relaxed)) {
+                        Err(format!("{} is not downloaded or cached, it cannot play offline", track.title))
+                    } else {
+                        Ok(stream)
+                    }
+                });
+            *result.lock().unwrap() = Some(resolved);
+        });
+        resolving
+    }
+
+    /// Offline when switched to offline mode, or when the network is gone
+    pub fn is_offline(&self) -> bool {
+        self.offline || !self.online.load(Ordering::Relaxed)
+    }
+
+    pub fn toggle_offline(&mut self) {
+        self.offline = !self.offline;
+        self.notice = Some(
+            if self.offline {
+                "Offline mode: searching the library, downloads and cache only".to_string()
+            } else if self.is_offline() {
+                "Offline mode off, but there is no network".to_string()
+            } else {
+                "Offline mode off".to_string()
+            }
+        );
+    }
+
+    /// Replaces the queue: plays the first track and queues the rest
+    pub fn play_all(&mut self, tracks: Vec<SearchResult>) -> Result<(), Box<dyn Error>> {
+        let mut tracks = VecDeque::from(tracks);
+        if let Some(first) = tracks.pop_front() {
+            self.play(first)?;
+            self.queue = tracks;
+        }
+        Ok(())
+    }
+
+    /// Starts the next queued track, returns false when the queue is empty
+    pub fn play_next(&mut self) -> Result<bool, Box<dyn Error>> {
+        match self.queue.pop_front() {
+            Some(next) => {
+                self.play(next)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Seconds into the playing track. Silences smart speed skipped count
+    /// as played.
+    pub fn position(&self) -> u64 {
+        let skipped = self.playback_report.lock().unwrap().silence_saved;
+        self.played_before + ((self.played_since_resumed() + skipped) as u64)
+    }
+
+    fn played_since_resumed(&self) -> f64 {
+        self.playing_since.map_or(0.0, |since| since.elapsed().as_secs_f64() * self.effects.speed)
+    }
+
+    /// Called on every tick of the main loop. Keeps up with playback moving
+    /// on to the prefetched track, and starts the next queued track once
+    /// playback has ended without one.
+    pub fn on_tick(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(status) = self.keep_status.lock().unwrap().take() {
+            self.notice = Some(status);
+        }
+        if self.daemon.is_some() {
+            self.sync_with_daemon();
+            return Ok(());
+        }
+        self.start_launching()?;
+        let (advances, finished) = match &mut self.playback {
+            Some(playback) => (playback.advanced(), playback.is_finished()),
+            None => {
+                return Ok(());
+            }
+        };
+        if advances > self.advances {
+            self.advances = advances;
+            self.finish_track();
+            if let Some(next) = self.prefetched.take() {
+                if self.queue.front().is_some_and(|front| same_track(front, &next.track)) {
+                    self.queue.pop_front();
+                }
+                self.effects = next.effects;
+                self.playback_report = next.report;
+                self.played_before = 0;
+                self.playing_since = if self.paused { None } else { Some(Instant::now()) };
+                self.prefetch_tried = false;
+                self.set_episode_played(&next.track, true);
+                self.now_playing = Some(next.track);
+            }
+        } else if finished {
+            if let Some(playback) = self.playback.take() {
+                playback.stop();
+            }
+            self.prefetched = None;
+            self.finish_track();
+            if !self.play_next()? {
+                self.paused = false;
+                self.end_of_queue();
+            }
+            return Ok(());
+        }
+        self.prefetch_next();
+        self.cache_next();
+        Ok(())
+    }
+
+    // Downloads the front of the queue into the cache while the current
+    // track plays, so it starts from disk when its turn comes
+    fn cache_next(&mut self) {
+        let Some(track) = self.queue.front() else {
+            return;
+        };
+        let requested = self.cache_requested.as_ref().is_some_and(|requested| same_track(requested, track));
+        if requested || self.is_offline() || !self.cache.enabled() || track.source == LOCAL || track.source == RADIO {
+            return;
+        }
+        let track = track.clone();
+        self.cache_requested = Some(track.clone());
+        if self.cache.contains(&track) {
+            return;
+        }
+        let Some(source) = self.sources.get(&track.source) else {
+            return;
+        };
+        // Resolving can take a while, so it happens off the main loop too
+        let cache = self.cache.clone();
+        thread::spawn(move || {
+            if let Ok(stream) = source.resolve(&track) {
+                cache.spawn_fill(&track, stream);
+            }
+        });
+    }
+
+    /// Reads the cache entries for the cache view
+    pub fn refresh_cache_entries(&mut self) {
+        self.cache_entries = self.cache.entries();
+        self.cache_index = self.cache_index.min(self.cache_entries.len().saturating_sub(1));
+    }
+
+    // Nothing left to play, the streaming view goes back to where playback
+    // was started from
+    fn end_of_queue(&mut self) {
+        self.now_playing = None;
+        if self.current_view == View::Streaming {
+            self.current_view = self.back_view;
+        }
+    }
+
+    // Played to the end, next time starts from the beginning
+    fn finish_track(&mut self) {
+        self.playing_since = None;
+        self.reload_positions();
+        if let Some(track) = &self.now_playing {
+            if self.positions.forget(track) {
+                let _ = self.positions.save();
+            }
+        }
+    }
+
+    // Starts decoding the front of the queue once the playing track is
+    // close to its end (tracks of unknown length right away), so it follows
+    // without a gap. Whatever stands in the way (a saved position to ask
+    // about, a live stream, a failing source) is left to `play_next`.
+    fn prefetch_next(&mut self) {
+        if self.prefetched.is_some() || !self.resumable || self.playback.is_none() {
+            return;
+        }
+        if let Some(prefetching) = &self.prefetching {
+            let Some(stream) = prefetching.take_result() else {
+                return;
+            };
+            let track = prefetching.track.clone();
+            self.prefetching = None;
+            // The queue may have changed while the stream was resolved
+            if self.queue.front().is_some_and(|front| same_track(front, &track)) {
+                if let Ok(stream) = stream {
+                    self.queue_decoder(track, stream);
+                }
+            }
+            return;
+        }
+        if self.prefetch_tried {
+            return;
+        }
+        let lead = PREFETCH_LEAD + self.config.crossfade;
+        let remaining = self.now_playing
+            .as_ref()
+            .and_then(|track| track.duration)
+            .map(|duration| (duration.saturating_sub(self.position()) as f64) / self.effects.speed);
+        if remaining.is_some_and(|remaining| remaining > lead) {
+            return;
+        }
+        let Some(track) = self.queue.front().cloned() else {
+            return;
+        };
+        self.prefetch_tried = true;
+        if self.positions.get(&track).is_some() {
+            return;
+        }
+        self.prefetching = Some(self.resolve_track(&track));
+        self.prefetch_next();
+    }
+
+    // Starts decoding the prefetched track behind the playing one
+    fn queue_decoder(&mut self, track: SearchResult, stream: Stream) {
+        if matches!(stream, Stream::Live(_)) {
+            return;
+        }
+        let keep = if matches!(stream, Stream::Extract(_)) { self.cache.begin(&track) } else { None };
+        let effects = PlaybackEffects {
+            speed: self.default_speed(&track),
+            gain: self.replay_gain(&track),
+            ..self.effects
+        };
+        let report = Arc::new(Mutex::new(PlaybackReport::default()));
+        // Never a live stream, so nothing is announced or recorded
+        let decoder = start_decoder(
+            &stream,
+            0,
+            effects,
+            Arc::new(Mutex::new(None)),
+            Arc::new(Mutex::new(StreamRecorder::new(&track.title, &self.config))),
+            Arc::clone(&report),
+            keep
+        );
+        let Ok(decoder) = decoder else {
+            return;
+        };
+        if let Some(playback) = &mut self.playback {
+            playback.queue_next(decoder);
+            self.prefetched = Some(Prefetched { track, effects, report });
+        }
+    }
+
+    /// R in the streaming view. Radio stations are recorded song by song
+    /// until R is pressed again, other tracks are saved to the download
+    /// folder from what has been streamed, or downloaded when that is not
+    /// possible.
+    pub fn keep_playing(&mut self) {
+        let Some(track) = self.now_playing.clone() else {
+            return;
+        };
+        if !self.resumable {
+            let mut recorder = self.recorder.lock().unwrap();
+            let notice = if recorder.is_recording() {
+                recorder.stop();
+                format!(
+                    "Recording stopped, {} songs saved to {}",
+                    recorder.saved.len(),
+                    self.config.download_dir.display()
+                )
+            } else {
+                match recorder.start() {
+                    Ok(()) => "Recording from the start of this song, every song goes into its own file".to_string(),
+                    Err(e) => format!("Could not record: {}", e),
+                }
+            };
+            self.notice = Some(notice);
+            return;
+        }
+        if track.source == LOCAL {
+            self.notice = Some(format!("{} is already on disk", track.title));
+            return;
+        }
+        if self.is_downloaded(&track) {
+            self.notice = Some(format!("{} is already downloaded", track.title));
+            return;
+        }
+
+        let location = self.sources.location(&track);
+        let config = self.config.clone();
+        let history = Arc::clone(&self.history);
+        let status = Arc::clone(&self.keep_status);
+        if let Some(path) = self.cache.lookup(&track) {
+            self.notice = Some(format!("Saving {}", track.title));
+            thread::spawn(move || {
+                let message = outcome_message(&track.title, keep_file(&track, &location, &path, &config, &history));
+                *status.lock().unwrap() = Some(message);
+            });
+            return;
+        }
+        let kept = track.clone();
+        let saved_once_written = self.cache.when_written(
+            &track,
+            Box::new(move |path| {
+                let message = match path {
+                    Some(path) => outcome_message(&kept.title, keep_file(&kept, &location, path, &config, &history)),
+                    None => format!("{} was not streamed to the end, nothing was saved", kept.title),
+                };
+                *status.lock().unwrap() = Some(message);
+            })
+        );
+        if saved_once_written {
+            self.notice = Some(format!("{} is saved once it has streamed completely", track.title));
+            return;
+        }
+        // Not streamed from the start, or not through the cache
+        match self.sources.get(&track.source).filter(|source| source.can_download()) {
+            Some(source) if !self.is_offline() => {
+                download_track(
+                    source,
+                    track,
+                    self.download_options,
+                    self.config.clone(),
+                    Arc::clone(&self.history),
+                    Arc::clone(&self.keep_status)
+                );
+            }
+            _ => {
+                self.notice = Some(format!("{} cannot be saved", track.title));
+            }
+        }
+    }
+
+    /// Stops playback, remembering the position of long tracks
     pub fn stop_streaming(&mut self) {
-        if let Some(mut process) = self.ffplay_process.take() {
-            let _ = process.kill();
-            let _ = process.wait();
+        if self.daemon.is_some() {
+            self.send_to_daemon("stop");
+            return;
         }
+        self.launching = None;
+        self.prefetched = None;
+        self.prefetching = None;
+        self.recorder.lock().unwrap().stop();
+        if let Some(playback) = self.playback.take() {
+            playback.stop();
+            let position = self.position();
+            self.reload_positions();
+            if let Some(track) = self.now_playing.as_ref().filter(|_| self.resumable) {
+                if self.positions.remember(track, position, &self.config) {
+                    let _ = self.positions.save();
+                }
+            }
+        }
+        self.playing_since = None;
         self.paused = false;
     }
 
+    /// Whether a track is playing or about to, here or in the daemon
+    pub fn is_playing(&self) -> bool {
+        match self.daemon {
+            Some(_) => self.now_playing.is_some(),
+            None => self.playback.is_some() || self.launching.is_some(),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        match self.daemon {
+            Some(_) => self.daemon_recording,
+            None => self.recorder.lock().unwrap().is_recording(),
+        }
+    }
+
+    /// Downloads `track` in the background. Attached to a daemon, it
+    /// downloads there and finishes even when this window is closed.
+    pub fn download(&mut self, source: Arc<dyn MusicSource>, track: SearchResult) {
+        if self.daemon.is_some() {
+            let title = track.title.clone();
+            let request =
+                json!({
+                "command": "download",
+                "track": track.to_json(),
+                "format": self.download_options.format.name(),
+                "quality": self.download_options.quality.name(),
+            });
+            if self.daemon_request(request).is_some() {
+                *self.download_status.lock().unwrap() = Some(format!("{} is downloading", title));
+            }
+            return;
+        }
+        download_track(
+            source,
+            track,
+            self.download_options,
+            self.config.clone(),
+            Arc::clone(&self.history),
+            Arc::clone(&self.download_status)
+        );
+    }
+
+    /// Downloads playlist entries, numbered by their position in it, into a
+    /// folder named after the playlist. In the daemon when attached.
+    pub fn download_playlist(
+        &mut self,
+        source: Arc<dyn MusicSource>,
+        playlist_title: String,
+        entries: Vec<(u32, SearchResult)>
+    ) {
+        if self.daemon.is_some() {
+            let tracks: Vec<Value> = entries
+                .iter()
+                .map(|(number, track)| {
+                    let mut json = track.to_json();
+                    json["number"] = json!(number);
+                    json
+                })
+                .collect();
+            let request =
+                json!({
+                "command": "download",
+                "playlist": playlist_title,
+                "tracks": tracks,
+                "format": self.download_options.format.name(),
+                "quality": self.download_options.quality.name(),
+            });
+            if self.daemon_request(request).is_some() {
+                *self.download_status.lock().unwrap() = Some(
+                    format!("{}: queued {} downloads", playlist_title, entries.len())
+                );
+            }
+            return;
+        }
+        download_playlist(
+            source,
+            playlist_title,
+            entries,
+            self.download_options,
+            self.config.clone(),
+            Arc::clone(&self.history),
+            Arc::clone(&self.download_status)
+        );
+    }
+
+    /// Leaves playback, the queue and downloads to the daemon when one is
+    /// running, starting one first when the config asks for it
+    pub fn attach_daemon(&mut self) {
+        self.daemon = DaemonClient::connect().or_else(|| {
+            if self.config.daemon { DaemonClient::spawn() } else { None }
+        });
+        if self.daemon.is_none() {
+            return;
+        }
+        self.sync_with_daemon();
+        // Pick up where the last window left off
+        if self.now_playing.is_some() {
+            self.back_view = self.current_view;
+            self.current_view = View::Streaming;
+        }
+    }
+
+    // Sends a request to the daemon. A refusal becomes the notice, a
+    // daemon that is gone leaves playback to this window again.
+    fn daemon_request(&mut self, request: Value) -> Option<Value> {
+        let client = self.daemon.as_mut()?;
+        match client.request(request) {
+            Ok(response) => {
+                if let Some(notice) = response["notice"].as_str() {
+                    self.notice = Some(notice.to_string());
+                }
+                Some(response)
+            }
+            Err(DaemonError::Refused(e)) => {
+                self.notice = Some(e);
+                None
+            }
+            Err(e @ DaemonError::Lost(_)) => {
+                self.notice = Some(format!("{}, playing here from now on", e));
+                self.daemon = None;
+                self.start_workers();
+                self.now_playing = None;
+                self.paused = false;
+                None
+            }
+        }
+    }
+
+    /// Sends a command without arguments, such as "pause" or "next"
+    pub fn send_to_daemon(&mut self, command: &str) {
+        self.daemon_request(json!({ "command": command }));
+    }
+
+    // Hands tracks queued here to the daemon, then mirrors what the daemon
+    // is playing for the streaming view
+    fn sync_with_daemon(&mut self) {
+        let appended =
+            self.queue.len() >= self.daemon_queue.len() &&
+            self.daemon_queue
+                .iter()
+                .zip(&self.queue)
+                .all(|(a, b)| same_track(a, b));
+        if !appended {
+            // Cleared or rearranged here
+            let tracks: Vec<Value> = self.queue.iter().map(SearchResult::to_json).collect();
+            self.daemon_request(json!({ "command": "queue", "tracks": tracks }));
+        } else if self.queue.len() > self.daemon_queue.len() {
+            // Only added to, so tracks the daemon moved on to meanwhile stay played
+            let tracks: Vec<Value> = self.queue
+                .iter()
+                .skip(self.daemon_queue.len())
+                .map(SearchResult::to_json)
+                .collect();
+            self.daemon_request(json!({ "command": "enqueue", "tracks": tracks }));
+        }
+        let Some(status) = self.daemon_request(json!({ "command": "status" })) else {
+            return;
+        };
+        let status = DaemonStatus::from_json(&status);
+        if *self.download_status.lock().unwrap() != status.download {
+            // Downloads the daemon finished are in the history file
+            *self.history.lock().unwrap() = DownloadHistory::load();
+            *self.download_status.lock().unwrap() = status.download;
+        }
+        let ended = self.now_playing.is_some() && status.playing.is_none();
+        self.now_playing = status.playing;
+        if ended {
+            self.end_of_queue();
+        }
+        // `position` adds the silences smart speed skipped, the daemon's
+        // position already counts them
+        self.played_before = status.position.saturating_sub(status.silence_saved as u64);
+        self.playing_since = None;
+        self.paused = status.paused;
+        self.effects = status.effects;
+        *self.playback_report.lock().unwrap() = PlaybackReport {
+            silence_saved: status.silence_saved,
+            loudness: status.loudness,
+        };
+        *self.stream_title.lock().unwrap() = status.stream_title;
+        *self.visualization_data.lock().unwrap() = status.visualization;
+        self.daemon_recording = status.recording;
+        self.queue = status.queue.iter().cloned().collect();
+        self.daemon_queue = status.queue;
+    }
+
+    /// Pauses or resumes ffplay, nothing happens when nothing is playing
     pub fn toggle_pause(&mut self) -> Result<(), Box<dyn Error>> {
-        if let Some(process) = &self.ffplay_process {
-            let pid = process.id();
+        if let Some(playback) = &self.playback {
+            let pid = playback.id();
             let signal = if self.paused { "CONT" } else { "STOP" };
-            let status = Command::new("kill").args(&["-s", signal, &pid.to_string()]).status()?;
+            let status = Command::new("kill").args(["-s", signal, &pid.to_string()]).status()?;
             if status.success() {
                 self.paused = !self.paused;
+                if self.paused {
+                    self.played_before += self.played_since_resumed() as u64;
+                    self.playing_since = None;
+                } else {
+                    self.playing_since = Some(Instant::now());
+                }
                 Ok(())
             } else {
                 Err(format!("Failed to send {} signal to ffplay", signal).into())
             }
         } else {
-            Err("No ffplay process running".into())
+            Ok(())
         }
     }
 }
 
+// Shown while the stream of a track is being looked up
+fn loading_notice(track: &SearchResult) -> String {
+    format!("Loading {}...", track.title)
+}
+
+fn same_track(a: &SearchResult, b: &SearchResult) -> bool {
+    a.source == b.source && a.identifier == b.identifier
+}
+
+// Brings the on-disk index up to date in the background, searches use the
+// previous index until the rescan is done
+fn spawn_library_scan(dirs: Vec<PathBuf>, library: Arc<Mutex<Library>>) {
+    thread::spawn(move || {
+        let before = Library { tracks: library.lock().unwrap().tracks.clone() };
+        let scanned = before.rescan(&dirs);
+        let mut library = library.lock().unwrap();
+        library.merge_scan(&before, scanned);
+        let _ = library.save();
+    });
+}
+
 impl Drop for AppUi {
     fn drop(&mut self) {
-        self.stop_streaming();
+        // Playback left to a daemon goes on
+        if self.daemon.is_none() {
+            self.stop_streaming();
+        }
     }
 }
@@ -0,0 +1,338 @@
+// cache.rs
+// Recently streamed audio kept on disk, so played tracks start right away
+// the next time and the next queued track is ready before it is due.
+// Least recently played entries make room once the cache is full.
+use std::cmp::Reverse;
+use std::collections::{ HashMap, HashSet };
+use std::fs::{ self, File };
+use std::io::{ Read, Write };
+use std::path::{ Path, PathBuf };
+use std::process::{ Command, Stdio };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::SystemTime;
+use serde_json::{ Map, Value };
+use crate::app::SearchResult;
+use crate::config::{ cache_dir, Config };
+use crate::sources::Stream;
+
+const YT_DLP_PATH: &str = "yt-dlp";
+
+/// One cached track, for the cache view
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub title: String,
+    // None for files whose track was not recorded
+    pub track: Option<SearchResult>,
+    pub path: PathBuf,
+    pub size: u64,
+    pub used: SystemTime,
+}
+
+// Called with the finished entry, or None when it was thrown away
+type WrittenCallback = Box<dyn FnOnce(Option<&Path>) + Send>;
+
+/// The audio files in `cache_dir()/audio`, named after their track. The
+/// modification time of a file is when it was last played.
+#[derive(Clone)]
+pub struct AudioCache {
+    dir: PathBuf,
+    pub max_bytes: u64,
+    // Entries being written, by path
+    writing: Arc<Mutex<HashSet<PathBuf>>>,
+    // Waiting for entries being written
+    on_written: Arc<Mutex<HashMap<PathBuf, WrittenCallback>>>,
+}
+
+// FNV-1a, stable across builds unlike the standard library's hasher
+fn file_name(track: &SearchResult) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in format!("{}:{}", track.source, track.identifier).bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+fn is_entry_name(name: &str) -> bool {
+    name.len() == 16 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl AudioCache {
+    pub fn new(config: &Config) -> AudioCache {
+        AudioCache {
+            dir: cache_dir().join("audio"),
+            max_bytes: config.cache_size * 1024 * 1024,
+            writing: Arc::new(Mutex::new(HashSet::new())),
+            on_written: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    fn path(&self, track: &SearchResult) -> PathBuf {
+        self.dir.join(file_name(track))
+    }
+
+    fn tracks_path(&self) -> PathBuf {
+        self.dir.join("tracks.json")
+    }
+
+    /// The cached copy of `track`, marked as just played
+    pub fn lookup(&self, track: &SearchResult) -> Option<PathBuf> {
+        let path = self.path(track);
+        let file = File::options().append(true).open(&path).ok()?;
+        let _ = file.set_modified(SystemTime::now());
+        Some(path)
+    }
+
+    pub fn contains(&self, track: &SearchResult) -> bool {
+        self.path(track).exists()
+    }
+
+    /// Calls `callback` once the entry of `track` that is being written is
+    /// done. Returns false, without calling it, when nothing is being written.
+    pub fn when_written(&self, track: &SearchResult, callback: WrittenCallback) -> bool {
+        let path = self.path(track);
+        // Under the `writing` lock, so the entry cannot finish in between
+        let writing = self.writing.lock().unwrap();
+        if !writing.contains(&path) {
+            return false;
+        }
+        self.on_written.lock().unwrap().insert(path, callback);
+        true
+    }
+
+    /// Starts a new entry for `track`. None when caching is off, or the
+    /// track is already cached or being written.
+    pub fn begin(&self, track: &SearchResult) -> Option<PendingEntry> {
+        let path = self.path(track);
+        if !self.enabled() || path.exists() || !self.writing.lock().unwrap().insert(path.clone()) {
+            return None;
+        }
+        self.remember_track(&path, track);
+        let part = path.with_extension("part");
+        match File::create(&part) {
+            Ok(file) => Some(PendingEntry { cache: self.clone(), path, part, file, complete: false }),
+            Err(_) => {
+                self.writing.lock().unwrap().remove(&path);
+                None
+            }
+        }
+    }
+
+    /// Downloads `track` into the cache in the background
+    pub fn spawn_fill(&self, track: &SearchResult, stream: Stream) {
+        let Some(mut pending) = self.begin(track) else {
+            return;
+        };
+        thread::spawn(move || {
+            match stream {
+                Stream::Extract(page) => {
+                    let yt_dlp = Command::new(YT_DLP_PATH)
+                        .args(["-o", "-", "-f", "bestaudio", "--quiet", "--playlist-items", "1"])
+                        .arg(&page)
+                        .stdout(Stdio::piped())
+                        .stderr(Stdio::null())
+                        .spawn();
+                    let Ok(mut yt_dlp) = yt_dlp else {
+                        return;
+                    };
+                    let copied = std::io::copy(yt_dlp.stdout.as_mut().unwrap(), &mut pending).is_ok();
+                    if copied && yt_dlp.wait().is_ok_and(|status| status.success()) {
+                        pending.commit();
+                    }
+                }
+                Stream::Direct(url) if url.starts_with("http") => {
+                    let response = reqwest::blocking::get(&url).and_then(|response| response.error_for_status());
+                    if let Ok(mut response) = response {
+                        if std::io::copy(&mut response, &mut pending).is_ok() {
+                            pending.commit();
+                        }
+                    }
+                }
+                // Files on disk and live streams are not cached
+                _ => {}
+            }
+        });
+    }
+
+    /// Cached tracks, most recently played first
+    pub fn entries(&self) -> Vec<CacheEntry> {
+        let tracks = self.tracks();
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut entries: Vec<CacheEntry> = dir
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().to_str()?.to_string();
+                if !is_entry_name(&name) {
+                    return None;
+                }
+                let metadata = entry.metadata().ok()?;
+                let track = tracks.get(&name).and_then(SearchResult::from_json);
+                Some(CacheEntry {
+                    title: track.as_ref().map_or(name, |track| track.title.clone()),
+                    track,
+                    path: entry.path(),
+                    size: metadata.len(),
+                    used: metadata.modified().ok()?,
+                })
+            })
+            .collect();
+        entries.sort_by_key(|entry| Reverse(entry.used));
+        entries
+    }
+
+    pub fn remove(&self, entry: &CacheEntry) {
+        let _ = fs::remove_file(&entry.path);
+    }
+
+    pub fn clear(&self) {
+        for entry in self.entries() {
+            self.remove(&entry);
+        }
+        let _ = fs::remove_file(self.tracks_path());
+    }
+
+    /// Cached tracks with every word of the query in their title or artist
+    pub fn search(&self, query: &str) -> Vec<SearchResult> {
+        self.entries()
+            .into_iter()
+            .filter_map(|entry| entry.track)
+            .filter(|track| track.matches(query))
+            .collect()
+    }
+
+    // Drops the least recently played entries until the cache fits
+    fn evict(&self) {
+        let mut entries = self.entries();
+        let mut size: u64 = entries
+            .iter()
+            .map(|entry| entry.size)
+            .sum();
+        while size > self.max_bytes {
+            let Some(oldest) = entries.pop() else {
+                break;
+            };
+            self.remove(&oldest);
+            size -= oldest.size;
+        }
+    }
+
+    // The tracks are kept next to the files in `tracks.json`, by file name,
+    // so cached tracks can be found again without a network
+    fn tracks(&self) -> Map<String, Value> {
+        fs::read_to_string(self.tracks_path())
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+            .and_then(|json| json.as_object().cloned())
+            .unwrap_or_default()
+    }
+
+    fn remember_track(&self, path: &Path, track: &SearchResult) {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return;
+        };
+        if fs::create_dir_all(&self.dir).is_err() {
+            return;
+        }
+        let mut tracks = self.tracks();
+        // Tracks of evicted entries go as well
+        tracks.retain(|name, _| {
+            let path = self.dir.join(name);
+            path.exists() || path.with_extension("part").exists()
+        });
+        tracks.insert(name.to_string(), track.to_json());
+        if let Ok(contents) = serde_json::to_string_pretty(&Value::Object(tracks)) {
+            let _ = fs::write(self.tracks_path(), contents);
+        }
+    }
+}
+
+/// A cache entry being written. It only becomes part of the cache once
+/// `commit` is called, dropped before that it is thrown away.
+pub struct PendingEntry {
+    cache: AudioCache,
+    path: PathBuf,
+    part: PathBuf,
+    file: File,
+    complete: bool,
+}
+
+impl PendingEntry {
+    /// The whole track has been written
+    pub fn commit(mut self) {
+        if self.file.flush().is_ok() && fs::rename(&self.part, &self.path).is_ok() {
+            self.complete = true;
+            // Before the entry can be evicted
+            if let Some(callback) = self.finish() {
+                callback(Some(&self.path));
+            }
+            self.cache.evict();
+        }
+    }
+
+    // No longer being written. Takes the callback waiting for the entry
+    // while holding the `writing` lock, the same order `when_written` locks in.
+    fn finish(&self) -> Option<WrittenCallback> {
+        let mut writing = self.cache.writing.lock().unwrap();
+        writing.remove(&self.path);
+        self.cache.on_written.lock().unwrap().remove(&self.path)
+    }
+}
+
+impl Write for PendingEntry {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.file.write(bytes)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        if !self.complete {
+            let _ = fs::remove_file(&self.part);
+            if let Some(callback) = self.finish() {
+                callback(None);
+            }
+        }
+    }
+}
+
+/// Copies `source` into both `sink` and `pending`, committing the entry when
+/// the source ends and `finished` says the download went through
+pub fn tee_into(
+    mut source: impl Read,
+    mut sink: impl Write,
+    mut pending: PendingEntry,
+    finished: impl FnOnce() -> bool
+) {
+    let mut buffer = [0u8; 16384];
+    loop {
+        match source.read(&mut buffer) {
+            Ok(0) => {
+                break;
+            }
+            Ok(n) => {
+                // The player is gone, a partial copy is of no use
+                if sink.write_all(&buffer[..n]).is_err() || pending.write_all(&buffer[..n]).is_err() {
+                    return;
+                }
+            }
+            Err(_) => {
+                return;
+            }
+        }
+    }
+    drop(sink);
+    if finished() {
+        pending.commit();
+    }
+}
@@ -0,0 +1,469 @@
+// cli.rs
+// Subcommands for scripts: search, download and play without the TUI.
+// Results go to stdout, progress and errors to stderr.
+use std::path::Path;
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::Duration;
+use serde_json::{ json, Value };
+use crate::app::SearchResult;
+use crate::cache::AudioCache;
+use crate::config::Config;
+use crate::daemon::{ run_daemon, DaemonClient, DaemonError, DaemonStatus };
+use crate::download::{ outcome_message, AudioFormat, AudioQuality, DownloadOptions, Downloaded };
+use crate::history::DownloadHistory;
+use crate::library::Library;
+use crate::plugins::discover_plugins;
+use crate::podcasts::PodcastStore;
+use crate::radio::RadioFavorites;
+use crate::recorder::StreamRecorder;
+use crate::replaygain::GainCache;
+use crate::sources::{ MusicSource, SourceRegistry, Stream, LOCAL };
+use crate::stream::{ start_decoder, Playback, PlaybackEffects, PlaybackReport };
+use crate::ui::format_duration;
+
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+const USAGE: &str =
+    "Usage:
+  pjplayer                                   start the player
+  pjplayer search <query> [--source KEY] [--json]
+  pjplayer download <id|url> [--source KEY] [--format FORMAT] [--quality KBPS] [--json]
+  pjplayer play <id|url> [--source KEY]
+  pjplayer daemon                            keep playing in the background
+  pjplayer enqueue <id|url> [--source KEY]   queue a track in the daemon
+  pjplayer status [--json]
+  pjplayer pause | next | stop | quit
+
+Tracks are given as the SOURCE:IDENTIFIER ids search prints, as links, or as
+bare identifiers of --source. Formats: mp3, opus, m4a, flac, original.
+Qualities: best, 320, 256, 192, 128. With a daemon running, play hands the
+track to it and returns; enqueue, status, pause, next, stop and quit need one.";
+
+enum CliError {
+    // Bad arguments, answered with the usage
+    Usage(String),
+    Failed(String),
+}
+
+type CliResult = Result<(), CliError>;
+
+#[derive(Default)]
+struct Options {
+    arguments: Vec<String>,
+    source: Option<String>,
+    format: Option<AudioFormat>,
+    quality: Option<AudioQuality>,
+    json: bool,
+}
+
+fn option_value<'a>(args: &mut impl Iterator<Item = &'a String>, name: &str) -> Result<String, CliError> {
+    args.next()
+        .cloned()
+        .ok_or_else(|| CliError::Usage(format!("{} needs a value", name)))
+}
+
+fn parse_options(args: &[String]) -> Result<Options, CliError> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => {
+                options.json = true;
+            }
+            "--source" | "-s" => {
+                options.source = Some(option_value(&mut args, arg)?);
+            }
+            "--format" | "-f" => {
+                let name = option_value(&mut args, arg)?;
+                options.format = Some(
+                    AudioFormat::from_name(&name).ok_or_else(|| CliError::Usage(format!("Unknown format {}", name)))?
+                );
+            }
+            "--quality" | "-q" => {
+                let name = option_value(&mut args, arg)?;
+                options.quality = Some(
+                    AudioQuality::from_name(&name).ok_or_else(|| CliError::Usage(format!("Unknown quality {}", name)))?
+                );
+            }
+            // Everything after it is an argument, for queries starting with a dash
+            "--" => {
+                options.arguments.extend(args.by_ref().cloned());
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(CliError::Usage(format!("Unknown option {}", arg)));
+            }
+            _ => options.arguments.push(arg.clone()),
+        }
+    }
+    Ok(options)
+}
+
+/// The id a track goes by on the command line
+fn track_id(track: &SearchResult) -> String {
+    format!("{}:{}", track.source, track.identifier)
+}
+
+// What the subcommands share, loaded without the background work the TUI
+// starts (library scans, analysis, connectivity checks)
+struct Context {
+    config: Config,
+    history: Arc<Mutex<DownloadHistory>>,
+    cache: AudioCache,
+    sources: SourceRegistry,
+}
+
+impl Context {
+    fn load() -> Context {
+        let config = Config::load();
+        let history = Arc::new(Mutex::new(DownloadHistory::load()));
+        let cache = AudioCache::new(&config);
+        let mut sources = SourceRegistry::new(
+            &config,
+            Arc::new(Mutex::new(Library::load())),
+            Arc::new(Mutex::new(RadioFavorites::load())),
+            Arc::new(Mutex::new(PodcastStore::load())),
+            Arc::clone(&history),
+            cache.clone()
+        );
+        for plugin in discover_plugins(&config.plugin_dir) {
+            sources.register(Arc::new(plugin));
+        }
+        Context { config, history, cache, sources }
+    }
+
+    // The --source given, else the first source as in the TUI
+    fn source(&self, options: &Options) -> Result<Arc<dyn MusicSource>, CliError> {
+        match &options.source {
+            Some(key) =>
+                self.sources.get(key).ok_or_else(|| {
+                    let keys: Vec<&str> = self.sources
+                        .all()
+                        .iter()
+                        .map(|source| source.key())
+                        .collect();
+                    CliError::Usage(format!("Unknown source {}, sources are: {}", key, keys.join(", ")))
+                }),
+            None => Ok(Arc::clone(&self.sources.all()[0])),
+        }
+    }
+
+    fn check_network(&self, source: &dyn MusicSource) -> CliResult {
+        if self.config.offline && !source.works_offline() {
+            return Err(CliError::Failed(format!("{} needs a network connection, offline mode is on", source.label())));
+        }
+        Ok(())
+    }
+
+    // A link, a SOURCE:IDENTIFIER id, or an identifier of --source. Tracks
+    // given by identifier go by their location until the source reads the
+    // real title, the same as pasted links.
+    fn track(&self, argument: &str, options: &Options) -> Result<SearchResult, CliError> {
+        if let Some(track) = self.sources.track_from_url(argument) {
+            return Ok(track);
+        }
+        // Plugin keys have a colon of their own
+        let prefixed = self.sources
+            .all()
+            .iter()
+            .filter(|_| options.source.is_none())
+            .find_map(|source| {
+                let identifier = argument.strip_prefix(source.key())?.strip_prefix(':')?;
+                Some((Arc::clone(source), identifier))
+            });
+        let (source, identifier) = match prefixed {
+            Some(prefixed) => prefixed,
+            None => (self.source(options)?, argument),
+        };
+        let mut track = SearchResult {
+            identifier: identifier.to_string(),
+            title: String::new(),
+            artist: None,
+            year: None,
+            duration: None,
+            source: source.key().to_string(),
+        };
+        track.title = source.location(&track);
+        Ok(track)
+    }
+}
+
+/// Runs the subcommand in `args` (without the program name) and returns the
+/// exit code: 0 when it worked, 1 when it failed, 2 for bad arguments
+pub fn run(args: Vec<String>) -> i32 {
+    let Some((command, rest)) = args.split_first() else {
+        eprintln!("{}", USAGE);
+        return EXIT_USAGE;
+    };
+    if matches!(command.as_str(), "help" | "--help" | "-h") {
+        println!("{}", USAGE);
+        return 0;
+    }
+    let result = parse_options(rest).and_then(|options| {
+        match command.as_str() {
+            "search" => search(&Context::load(), &options),
+            "download" => download(&Context::load(), &options),
+            "play" => play(&Context::load(), &options),
+            "daemon" => run_daemon().map_err(|e| CliError::Failed(e.to_string())),
+            "enqueue" => enqueue(&Context::load(), &options),
+            "status" => status(&options),
+            "pause" | "next" | "stop" => send(command),
+            "quit" => quit(),
+            _ => Err(CliError::Usage(format!("Unknown command {}", command))),
+        }
+    });
+    match result {
+        Ok(()) => 0,
+        Err(CliError::Usage(message)) => {
+            eprintln!("pjplayer: {}\n\n{}", message, USAGE);
+            EXIT_USAGE
+        }
+        Err(CliError::Failed(message)) => {
+            eprintln!("pjplayer: {}", message);
+            EXIT_FAILURE
+        }
+    }
+}
+
+// One track per line: id, title, artist and length, separated by tabs.
+// Playlist and channel links list their entries.
+fn search(context: &Context, options: &Options) -> CliResult {
+    let query = options.arguments.join(" ");
+    if query.trim().is_empty() {
+        return Err(CliError::Usage("search needs a query".to_string()));
+    }
+    let source = context.source(options)?;
+    context.check_network(source.as_ref())?;
+    let results = if source.is_collection(&query) {
+        source.list_tracks(&query).map(|(_, entries)| entries)
+    } else {
+        source.search(&query)
+    };
+    let results = results.map_err(|e| CliError::Failed(format!("{} search failed: {}", source.label(), e)))?;
+    if options.json {
+        let results: Vec<Value> = results
+            .iter()
+            .map(|track| {
+                let mut json = track.to_json();
+                json["id"] = json!(track_id(track));
+                json
+            })
+            .collect();
+        println!("{}", Value::Array(results));
+    } else {
+        for track in &results {
+            println!(
+                "{}\t{}\t{}\t{}",
+                track_id(track),
+                track.title,
+                track.artist.as_deref().unwrap_or(""),
+                track.duration.map(format_duration).unwrap_or_default()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn single_argument<'a>(options: &'a Options, command: &str) -> Result<&'a str, CliError> {
+    match options.arguments.as_slice() {
+        [argument] => Ok(argument),
+        [] => Err(CliError::Usage(format!("{} needs a track id or link", command))),
+        _ => Err(CliError::Usage(format!("{} takes one track id or link", command))),
+    }
+}
+
+// Downloads into the download folder, with the configured format and quality
+// unless they are given
+fn download(context: &Context, options: &Options) -> CliResult {
+    let track = context.track(single_argument(options, "download")?, options)?;
+    let Some(source) = context.sources.get(&track.source) else {
+        return Err(CliError::Failed(format!("{} is not available", track.source)));
+    };
+    if !source.can_download() {
+        return Err(CliError::Failed(format!("{} tracks cannot be downloaded", source.label())));
+    }
+    context.check_network(source.as_ref())?;
+    let defaults = context.config.download_options;
+    let download_options = DownloadOptions {
+        format: options.format.unwrap_or(defaults.format),
+        quality: options.quality.unwrap_or(defaults.quality),
+    };
+    eprintln!("Downloading {}", track.title);
+    let outcome = source.download(&track, download_options, &context.config, &context.history, None);
+    if !options.json {
+        return match outcome {
+            Ok(outcome) => {
+                println!("{}", outcome_message(&track.title, Ok(outcome)));
+                Ok(())
+            }
+            Err(e) => Err(CliError::Failed(outcome_message(&track.title, Err(e)))),
+        };
+    }
+    let (status, path, warning) = match outcome {
+        Ok(Downloaded::Saved(warning)) => {
+            let history = context.history.lock().unwrap();
+            let path = history.find(&track.source, &track.identifier).map(|entry| entry.path.clone());
+            ("saved", path, warning)
+        }
+        Ok(Downloaded::Skipped) => ("skipped", None, None),
+        Ok(Downloaded::Duplicate(path)) => ("duplicate", Some(path), None),
+        Err(e) => {
+            return Err(CliError::Failed(outcome_message(&track.title, Err(e))));
+        }
+    };
+    let result =
+        json!({
+        "id": track_id(&track),
+        "status": status,
+        "path": path.map(|path| path.to_string_lossy().to_string()),
+        "warning": warning,
+    });
+    println!("{}", result);
+    Ok(())
+}
+
+// Plays one track to its end, or a radio station until interrupted, with
+// the configured speed and effects. The cached copy plays when there is one.
+// A running daemon plays it instead, and this returns right away.
+fn play(context: &Context, options: &Options) -> CliResult {
+    let track = context.track(single_argument(options, "play")?, options)?;
+    if let Some(mut daemon) = DaemonClient::connect() {
+        let response = daemon.request(json!({ "command": "play", "track": track.to_json() })).map_err(daemon_failed)?;
+        eprintln!("Playing {} in the daemon", track.title);
+        print_notice(&response);
+        return Ok(());
+    }
+    let stream = match context.cache.lookup(&track) {
+        Some(path) => Stream::Direct(path.to_string_lossy().to_string()),
+        None => {
+            let Some(source) = context.sources.get(&track.source) else {
+                return Err(CliError::Failed(format!("{} is not available", track.source)));
+            };
+            let stream = source
+                .resolve(&track)
+                .map_err(|e| CliError::Failed(format!("Could not play {}: {}", track.title, e)))?;
+            if stream.is_remote() && context.config.offline {
+                return Err(
+                    CliError::Failed(format!("{} is not downloaded or cached, it cannot play offline", track.title))
+                );
+            }
+            stream
+        }
+    };
+    let live = matches!(stream, Stream::Live(_));
+    let gain = if track.source == LOCAL {
+        GainCache::load()
+            .get(Path::new(&track.identifier))
+            .and_then(|entry| entry.gain(context.config.replaygain))
+    } else {
+        None
+    };
+    let effects = PlaybackEffects {
+        speed: if live { 1.0 } else { context.config.playback_speed.get(&track.source).copied().unwrap_or(1.0) },
+        gain,
+        smart_speed: context.config.smart_speed && !live,
+        normalize: context.config.normalize_loudness,
+    };
+    let keep = if matches!(stream, Stream::Extract(_)) { context.cache.begin(&track) } else { None };
+    let stream_title = Arc::new(Mutex::new(None));
+    let decoder = start_decoder(
+        &stream,
+        0,
+        effects,
+        Arc::clone(&stream_title),
+        Arc::new(Mutex::new(StreamRecorder::new(&track.title, &context.config))),
+        Arc::new(Mutex::new(PlaybackReport::default())),
+        keep
+    ).map_err(|e| CliError::Failed(format!("Could not play {}: {}", track.title, e)))?;
+    let mut playback = Playback::start(decoder, 0.0, Arc::new(Mutex::new(vec![0; 10]))).map_err(|e|
+        CliError::Failed(format!("Could not start ffplay: {}", e))
+    )?;
+    eprintln!("Playing {}", track.title);
+    let mut announced: Option<String> = None;
+    while !playback.is_finished() {
+        let title = stream_title.lock().unwrap().clone();
+        if title.is_some() && title != announced {
+            eprintln!("Now playing: {}", title.as_deref().unwrap_or(""));
+            announced = title;
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+    Ok(())
+}
+
+fn daemon_failed(e: DaemonError) -> CliError {
+    CliError::Failed(e.to_string())
+}
+
+fn daemon() -> Result<DaemonClient, CliError> {
+    DaemonClient::connect().ok_or_else(|| {
+        CliError::Failed("No daemon is running, start one with pjplayer daemon".to_string())
+    })
+}
+
+fn print_notice(response: &Value) {
+    if let Some(notice) = response["notice"].as_str() {
+        eprintln!("{}", notice);
+    }
+}
+
+// Commands without arguments: pause, next and stop
+fn send(command: &str) -> CliResult {
+    let response = daemon()?.request(json!({ "command": command })).map_err(daemon_failed)?;
+    print_notice(&response);
+    Ok(())
+}
+
+fn enqueue(context: &Context, options: &Options) -> CliResult {
+    let track = context.track(single_argument(options, "enqueue")?, options)?;
+    let request = json!({ "command": "enqueue", "tracks": [track.to_json()] });
+    let response = daemon()?.request(request).map_err(daemon_failed)?;
+    print_notice(&response);
+    Ok(())
+}
+
+// What is playing, then the queue and the last download, one per line
+fn status(options: &Options) -> CliResult {
+    let response = daemon()?.request(json!({ "command": "status" })).map_err(daemon_failed)?;
+    if options.json {
+        println!("{}", response);
+        return Ok(());
+    }
+    let status = DaemonStatus::from_json(&response);
+    match &status.playing {
+        Some(track) => {
+            let length = track.duration.map(|duration| format!(" / {}", format_duration(duration))).unwrap_or_default();
+            println!(
+                "{}\t{}\t{}\t{}{}",
+                if status.paused { "paused" } else { "playing" },
+                track_id(track),
+                track.title,
+                format_duration(status.position),
+                length
+            );
+        }
+        None => println!("stopped"),
+    }
+    if let Some(title) = &status.stream_title {
+        println!("song\t{}", title);
+    }
+    for track in &status.queue {
+        println!("queued\t{}\t{}", track_id(track), track.title);
+    }
+    if let Some(download) = &status.download {
+        println!("download\t{}", download);
+    }
+    if let Some(notice) = &status.notice {
+        eprintln!("{}", notice);
+    }
+    Ok(())
+}
+
+fn quit() -> CliResult {
+    match daemon()?.request(json!({ "command": "quit" })) {
+        // Gone before the reply made it out is as good as a reply
+        Ok(_) | Err(DaemonError::Lost(_)) => Ok(()),
+        Err(e) => Err(daemon_failed(e)),
+    }
+}
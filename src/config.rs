@@ -0,0 +1,201 @@
+// config.rs
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use serde_json::Value;
+use crate::download::{ AudioFormat, AudioQuality, DownloadOptions };
+use crate::naming::{ CollisionPolicy, DEFAULT_TEMPLATE };
+use crate::radio::DEFAULT_DIRECTORY_URL;
+use crate::replaygain::GainMode;
+use crate::stream::{ clamp_speed, MAX_CROSSFADE };
+
+fn home_dir() -> PathBuf {
+    let home_dir = env::var("HOME").expect("Could not find home directory");
+    PathBuf::from(home_dir)
+}
+
+/// `$XDG_CONFIG_HOME/pjplayer`, falling back to `~/.config/pjplayer`
+pub fn config_dir() -> PathBuf {
+    match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("pjplayer"),
+        _ => home_dir().join(".config").join("pjplayer"),
+    }
+}
+
+/// `$XDG_DATA_HOME/pjplayer`, falling back to `~/.local/share/pjplayer`
+pub fn data_dir() -> PathBuf {
+    match env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("pjplayer"),
+        _ => home_dir().join(".local").join("share").join("pjplayer"),
+    }
+}
+
+/// `$XDG_CACHE_HOME/pjplayer`, falling back to `~/.cache/pjplayer`
+pub fn cache_dir() -> PathBuf {
+    match env::var("XDG_CACHE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("pjplayer"),
+        _ => home_dir().join(".cache").join("pjplayer"),
+    }
+}
+
+/// Where the daemon listens: `$XDG_RUNTIME_DIR/pjplayer.sock`, or the cache
+/// directory without one
+pub fn socket_path() -> PathBuf {
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("pjplayer.sock"),
+        _ => cache_dir().join("daemon.sock"),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub download_dir: PathBuf,
+    pub filename_template: String,
+    pub on_collision: CollisionPolicy,
+    pub download_options: DownloadOptions,
+    // Folders indexed for the local library
+    pub library_dirs: Vec<PathBuf>,
+    // Executables offered as extra sources, see PLUGINS.md
+    pub plugin_dir: PathBuf,
+    // radio-browser compatible station directory
+    pub radio_directory_url: String,
+    // Tracks at least this many seconds long remember where they stopped
+    pub resume_min_length: u64,
+    // Stopped past this share of the track, it counts as finished
+    pub resume_finished_percent: u64,
+    // Default playback speed by source key
+    pub playback_speed: HashMap<String, f64>,
+    // Spoken-word filters switched on at start
+    pub smart_speed: bool,
+    pub normalize_loudness: bool,
+    pub replaygain: GainMode,
+    // Whether analyzed gains are also written into the files' tags. Off
+    // unless asked for, writing tags rewrites the files.
+    pub replaygain_tags: bool,
+    // Seconds consecutive tracks overlap, 0 plays them back to back
+    pub crossfade: f64,
+    // Megabytes of streamed audio kept on disk, 0 turns the cache off
+    pub cache_size: u64,
+    // Start in offline mode
+    pub offline: bool,
+    // Start a daemon from the TUI when none is running
+    pub daemon: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let download_dir = home_dir().join("Downloads");
+        Config {
+            download_dir: download_dir.clone(),
+            filename_template: DEFAULT_TEMPLATE.to_string(),
+            on_collision: CollisionPolicy::NumberSuffix,
+            download_options: DownloadOptions::default(),
+            library_dirs: vec![download_dir],
+            plugin_dir: config_dir().join("plugins"),
+            radio_directory_url: DEFAULT_DIRECTORY_URL.to_string(),
+            resume_min_length: 600,
+            resume_finished_percent: 95,
+            playback_speed: HashMap::new(),
+            smart_speed: false,
+            normalize_loudness: false,
+            replaygain: GainMode::Track,
+            replaygain_tags: false,
+            crossfade: 0.0,
+            cache_size: 1024,
+            offline: false,
+            daemon: false,
+        }
+    }
+}
+
+impl Config {
+    /// Reads `config.json` from the config directory. A missing file, a
+    /// broken file or a missing key all fall back to the defaults.
+    pub fn load() -> Config {
+        let path = config_dir().join("config.json");
+        let json = fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+            .unwrap_or(Value::Null);
+        Config::from_json(&json)
+    }
+
+    fn from_json(json: &Value) -> Config {
+        let mut config = Config::default();
+        if let Some(dir) = json["download_dir"].as_str() {
+            config.download_dir = expand_home(dir);
+            config.library_dirs = vec![config.download_dir.clone()];
+        }
+        if let Some(dirs) = json["library_dirs"].as_array() {
+            config.library_dirs = dirs
+                .iter()
+                .filter_map(|dir| dir.as_str())
+                .map(expand_home)
+                .collect();
+        }
+        if let Some(dir) = json["plugin_dir"].as_str() {
+            config.plugin_dir = expand_home(dir);
+        }
+        if let Some(url) = json["radio_directory_url"].as_str() {
+            config.radio_directory_url = url.to_string();
+        }
+        if let Some(length) = json["resume_min_length"].as_u64() {
+            config.resume_min_length = length;
+        }
+        if let Some(percent) = json["resume_finished_percent"].as_u64() {
+            config.resume_finished_percent = percent.min(100);
+        }
+        if let Some(speeds) = json["playback_speed"].as_object() {
+            config.playback_speed = speeds
+                .iter()
+                .filter_map(|(source, speed)| Some((source.clone(), clamp_speed(speed.as_f64()?))))
+                .collect();
+        }
+        if let Some(smart_speed) = json["smart_speed"].as_bool() {
+            config.smart_speed = smart_speed;
+        }
+        if let Some(normalize) = json["normalize_loudness"].as_bool() {
+            config.normalize_loudness = normalize;
+        }
+        if let Some(mode) = json["replaygain"].as_str().and_then(GainMode::from_name) {
+            config.replaygain = mode;
+        }
+        if let Some(write_tags) = json["replaygain_tags"].as_bool() {
+            config.replaygain_tags = write_tags;
+        }
+        if let Some(crossfade) = json["crossfade"].as_f64() {
+            config.crossfade = crossfade.clamp(0.0, MAX_CROSSFADE);
+        }
+        if let Some(size) = json["cache_size"].as_u64() {
+            config.cache_size = size;
+        }
+        if let Some(offline) = json["offline"].as_bool() {
+            config.offline = offline;
+        }
+        if let Some(daemon) = json["daemon"].as_bool() {
+            config.daemon = daemon;
+        }
+        if let Some(template) = json["filename_template"].as_str() {
+            config.filename_template = template.to_string();
+        }
+        if let Some(policy) = json["on_collision"].as_str().and_then(CollisionPolicy::from_name) {
+            config.on_collision = policy;
+        }
+        if let Some(format) = json["audio_format"].as_str().and_then(AudioFormat::from_name) {
+            config.download_options.format = format;
+        }
+        if let Some(quality) = json["audio_quality"].as_str().and_then(AudioQuality::from_name) {
+            config.download_options.quality = quality;
+        }
+        config
+    }
+}
+
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => home_dir().join(rest),
+        None if path == "~" => home_dir(),
+        None => PathBuf::from(path),
+    }
+}
@@ -0,0 +1,423 @@
+// daemon.rs
+// Playback, the queue and downloads in a background process, so music keeps
+// playing once the terminal is closed. The TUI and the command line attach
+// over a Unix socket, one JSON object per line each way (see DAEMON.md).
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{ BufRead, BufReader, Write };
+use std::os::unix::net::{ UnixListener, UnixStream };
+use std::os::unix::process::CommandExt;
+use std::process::{ Command, Stdio };
+use std::sync::mpsc::{ self, RecvTimeoutError, Sender };
+use std::sync::Arc;
+use std::thread;
+use std::time::{ Duration, Instant };
+use serde_json::{ json, Value };
+use crate::app::{ AppUi, SearchResult };
+use crate::config::socket_path;
+use crate::download::{ download_playlist, download_track, AudioFormat, AudioQuality, DownloadOptions };
+use crate::stream::PlaybackEffects;
+
+// Same as the TUI's tick
+const TICK: Duration = Duration::from_millis(250);
+// How long a daemon started from the TUI gets to open its socket
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+// A daemon that hangs should not hang its clients with it
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the daemon is playing, as the `status` command reports it
+#[derive(Debug, Default)]
+pub struct DaemonStatus {
+    pub playing: Option<SearchResult>,
+    // Seconds into the track
+    pub position: u64,
+    pub paused: bool,
+    pub effects: PlaybackEffects,
+    pub silence_saved: f64,
+    pub loudness: Option<f64>,
+    // Song the radio station announced
+    pub stream_title: Option<String>,
+    pub recording: bool,
+    pub queue: Vec<SearchResult>,
+    // The last download's progress or outcome
+    pub download: Option<String>,
+    // Anything the daemon had to say since the last status, such as a track
+    // that failed to play
+    pub notice: Option<String>,
+    pub visualization: Vec<u8>,
+}
+
+impl DaemonStatus {
+    fn of(app: &mut AppUi) -> DaemonStatus {
+        let position = app.position();
+        let report = app.playback_report.lock().unwrap();
+        DaemonStatus {
+            playing: app.now_playing.clone().filter(|_| app.playback.is_some()),
+            position,
+            paused: app.paused,
+            effects: app.effects,
+            silence_saved: report.silence_saved,
+            loudness: report.loudness,
+            stream_title: app.stream_title.lock().unwrap().clone(),
+            recording: app.recorder.lock().unwrap().is_recording(),
+            queue: app.queue.iter().cloned().collect(),
+            download: app.download_status.lock().unwrap().clone(),
+            notice: app.notice.take(),
+            visualization: app.visualization_data.lock().unwrap().clone(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "playing": self.playing.as_ref().map(SearchResult::to_json),
+            "position": self.position,
+            "paused": self.paused,
+            "speed": self.effects.speed,
+            "gain": self.effects.gain,
+            "smart_speed": self.effects.smart_speed,
+            "normalize": self.effects.normalize,
+            "silence_saved": self.silence_saved,
+            "loudness": self.loudness,
+            "stream_title": self.stream_title,
+            "recording": self.recording,
+            "queue": self.queue.iter().map(SearchResult::to_json).collect::<Vec<Value>>(),
+            "download": self.download,
+            "notice": self.notice,
+            "visualization": self.visualization,
+        })
+    }
+
+    pub fn from_json(json: &Value) -> DaemonStatus {
+        let text = |key: &str| json[key].as_str().map(str::to_string);
+        DaemonStatus {
+            playing: SearchResult::from_json(&json["playing"]),
+            position: json["position"].as_u64().unwrap_or(0),
+            paused: json["paused"].as_bool().unwrap_or(false),
+            effects: PlaybackEffects {
+                speed: json["speed"].as_f64().unwrap_or(1.0),
+                gain: json["gain"].as_f64(),
+                smart_speed: json["smart_speed"].as_bool().unwrap_or(false),
+                normalize: json["normalize"].as_bool().unwrap_or(false),
+            },
+            silence_saved: json["silence_saved"].as_f64().unwrap_or(0.0),
+            loudness: json["loudness"].as_f64(),
+            stream_title: text("stream_title"),
+            recording: json["recording"].as_bool().unwrap_or(false),
+            queue: tracks(&json["queue"]),
+            download: text("download"),
+            notice: text("notice"),
+            visualization: json["visualization"]
+                .as_array()
+                .map(|bars| {
+                    bars.iter()
+                        .filter_map(|bar| bar.as_u64())
+                        .map(|bar| bar.min(u8::MAX as u64) as u8)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn tracks(json: &Value) -> Vec<SearchResult> {
+    json.as_array()
+        .map(|items| items.iter().filter_map(SearchResult::from_json).collect())
+        .unwrap_or_default()
+}
+
+#[derive(Debug)]
+pub enum DaemonError {
+    // The daemon answered, but could not do it
+    Refused(String),
+    // The connection broke, the daemon is gone
+    Lost(String),
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DaemonError::Refused(e) => write!(f, "{}", e),
+            DaemonError::Lost(e) => write!(f, "Lost the connection to the daemon: {}", e),
+        }
+    }
+}
+
+/// A connection to the running daemon
+pub struct DaemonClient {
+    writer: UnixStream,
+    reader: BufReader<UnixStream>,
+}
+
+impl DaemonClient {
+    /// None when no daemon is running
+    pub fn connect() -> Option<DaemonClient> {
+        let stream = UnixStream::connect(socket_path()).ok()?;
+        stream.set_read_timeout(Some(REPLY_TIMEOUT)).ok()?;
+        let reader = BufReader::new(stream.try_clone().ok()?);
+        Some(DaemonClient { writer: stream, reader })
+    }
+
+    /// Starts a daemon in the background and connects to it
+    pub fn spawn() -> Option<DaemonClient> {
+        Command::new(std::env::current_exe().ok()?)
+            .arg("daemon")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            // Out of the terminal's process group, so closing the terminal
+            // does not hang it up along with the TUI
+            .process_group(0)
+            .spawn()
+            .ok()?;
+        let started = Instant::now();
+        while started.elapsed() < START_TIMEOUT {
+            if let Some(client) = DaemonClient::connect() {
+                return Some(client);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        None
+    }
+
+    /// Sends one request and waits for the reply
+    pub fn request(&mut self, request: Value) -> Result<Value, DaemonError> {
+        writeln!(self.writer, "{}", request).map_err(|e| DaemonError::Lost(e.to_string()))?;
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => {
+                return Err(DaemonError::Lost("the daemon closed the connection".to_string()));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                return Err(DaemonError::Lost(e.to_string()));
+            }
+        }
+        let response: Value = serde_json::from_str(&line).map_err(|e| DaemonError::Lost(e.to_string()))?;
+        if response["ok"].as_bool() == Some(true) {
+            Ok(response)
+        } else {
+            Err(DaemonError::Refused(response["error"].as_str().unwrap_or("Unknown error").to_string()))
+        }
+    }
+}
+
+// A request and where its reply goes
+type Request = (Value, Sender<Value>);
+
+/// Runs the daemon until a client sends `quit`. Clients are served on threads
+/// of their own, their requests are carried out one at a time here, between
+/// the ticks that keep playback going.
+pub fn run_daemon() -> Result<(), Box<dyn Error>> {
+    if DaemonClient::connect().is_some() {
+        return Err("A daemon is already running".into());
+    }
+    let path = socket_path();
+    // Left over from a daemon that did not shut down cleanly
+    let _ = fs::remove_file(&path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    let (sender, requests) = mpsc::channel::<Request>();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let sender = sender.clone();
+            thread::spawn(move || serve_client(stream, sender));
+        }
+    });
+
+    let mut app = AppUi::new();
+    app.start_workers();
+    let mut last_tick = Instant::now();
+    loop {
+        match requests.recv_timeout(TICK.saturating_sub(last_tick.elapsed())) {
+            Ok((request, reply)) => {
+                let quit = request["command"] == "quit";
+                let _ = reply.send(handle_request(&mut app, &request));
+                if quit {
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                break;
+            }
+        }
+        if last_tick.elapsed() >= TICK {
+            // A track that fails to start is reported, the daemon goes on
+            if let Err(e) = app.on_tick().and_then(|_| resume_pending(&mut app)) {
+                app.notice = Some(e.to_string());
+            }
+            last_tick = Instant::now();
+        }
+    }
+    let _ = fs::remove_file(&path);
+    Ok(())
+}
+
+fn serve_client(stream: UnixStream, requests: Sender<Request>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => {
+                let (reply, response) = mpsc::channel();
+                if requests.send((request, reply)).is_err() {
+                    return;
+                }
+                match response.recv() {
+                    Ok(response) => response,
+                    Err(_) => {
+                        return;
+                    }
+                }
+            }
+            Err(e) => json!({ "ok": false, "error": format!("Not a JSON request: {}", e) }),
+        };
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+// No one is there to answer the resume question, long tracks pick up where
+// they were stopped
+fn resume_pending(app: &mut AppUi) -> Result<(), Box<dyn Error>> {
+    if let Some((track, position)) = app.pending_resume.take() {
+        app.start_playback(track, position)?;
+    }
+    Ok(())
+}
+
+fn handle_request(app: &mut AppUi, request: &Value) -> Value {
+    let command = request["command"].as_str().unwrap_or("");
+    if command == "status" {
+        let mut status = DaemonStatus::of(app).to_json();
+        status["ok"] = json!(true);
+        return status;
+    }
+    match run_command(app, command, request) {
+        Ok(()) => json!({ "ok": true, "notice": app.notice.take() }),
+        Err(e) => json!({ "ok": false, "error": e.to_string() }),
+    }
+}
+
+fn run_command(app: &mut AppUi, command: &str, request: &Value) -> Result<(), Box<dyn Error>> {
+    match command {
+        "play" => {
+            let track = SearchResult::from_json(&request["track"]).ok_or("play needs a track")?;
+            match request["position"].as_u64() {
+                Some(position) => app.start_playback(track, position)?,
+                None => {
+                    app.play(track)?;
+                    resume_pending(app)?;
+                }
+            }
+            // The track could not be resolved, the notice says why
+            if !app.is_playing() {
+                return Err(app.notice.take().unwrap_or_else(|| "Could not play the track".to_string()).into());
+            }
+        }
+        "queue" => {
+            app.queue = tracks(&request["tracks"]).into();
+        }
+        "enqueue" => {
+            app.queue.extend(tracks(&request["tracks"]));
+            // Nothing playing yet, so the queue starts right away
+            if !app.is_playing() {
+                app.play_next()?;
+                resume_pending(app)?;
+            }
+        }
+        "next" => {
+            if !app.play_next()? {
+                return Err("The queue is empty".into());
+            }
+            resume_pending(app)?;
+        }
+        "pause" => {
+            if app.playback.is_none() {
+                return Err("Nothing is playing".into());
+            }
+            app.toggle_pause()?;
+        }
+        "stop" => app.stop_streaming(),
+        "faster" => app.change_speed(true)?,
+        "slower" => app.change_speed(false)?,
+        "smart_speed" => app.toggle_smart_speed()?,
+        "normalize" => app.toggle_normalize()?,
+        "replaygain" => app.cycle_gain_mode()?,
+        "keep" => app.keep_playing(),
+        "download" => download(app, request)?,
+        "quit" => {}
+        _ => {
+            return Err(format!("Unknown command {}", command).into());
+        }
+    }
+    Ok(())
+}
+
+// One `track`, or the `tracks` of a `playlist` into a folder named after it
+fn download(app: &mut AppUi, request: &Value) -> Result<(), Box<dyn Error>> {
+    let options = DownloadOptions {
+        format: request["format"].as_str().and_then(AudioFormat::from_name).unwrap_or(app.download_options.format),
+        quality: request["quality"].as_str().and_then(AudioQuality::from_name).unwrap_or(app.download_options.quality),
+    };
+    // Tracks of a playlist carry their position in it, or go in the order sent
+    let entries: Vec<(u32, SearchResult)> = match request["playlist"].as_str() {
+        Some(_) =>
+            request["tracks"]
+                .as_array()
+                .map(|items| {
+                    items
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, item)| {
+                            let number = item["number"].as_u64().unwrap_or((i as u64) + 1);
+                            Some((number as u32, SearchResult::from_json(item)?))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        None => SearchResult::from_json(&request["track"]).map(|track| (1, track)).into_iter().collect(),
+    };
+    let Some((_, first)) = entries.first() else {
+        return Err("download needs a track".into());
+    };
+    let source = app.sources
+        .get(&first.source)
+        .filter(|source| source.can_download())
+        .ok_or_else(|| format!("{} tracks cannot be downloaded", app.sources.label(&first.source)))?;
+    match request["playlist"].as_str() {
+        Some(title) => {
+            download_playlist(
+                source,
+                title.to_string(),
+                entries,
+                options,
+                app.config.clone(),
+                Arc::clone(&app.history),
+                Arc::clone(&app.download_status)
+            );
+        }
+        None => {
+            download_track(
+                source,
+                first.clone(),
+                options,
+                app.config.clone(),
+                Arc::clone(&app.history),
+                Arc::clone(&app.download_status)
+            );
+        }
+    }
+    Ok(())
+}
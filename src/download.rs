@@ -3,107 +3,530 @@ use std::process::{ Command, Stdio };
 use std::sync::{ Arc, Mutex };
 use std::fs::{ self, File };
 use std::thread;
-use std::env;
-use std::path::PathBuf;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+use std::path::{ Path, PathBuf };
 use serde_json::Value;
+use crate::app::SearchResult;
+use crate::config::Config;
+use crate::history::DownloadHistory;
+use crate::naming::{ render_template, resolve_collision, sanitize_component };
+use crate::search::archive_field;
+use crate::sources::{ MusicSource, SourceResult };
+use crate::tags::{ unique_suffix, write_tags, TrackMetadata };
 
 // const YT_DLP_PATH: &str = "bin/yt-dlp";
 const YT_DLP_PATH: &str = "yt-dlp";
+const FFPROBE_PATH: &str = "ffprobe";
 
-fn get_download_path() -> PathBuf {
-    let home_dir = env::var("HOME").expect("Could not find home directory");
-    PathBuf::from(home_dir).join("Downloads")
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioFormat {
+    Mp3,
+    Opus,
+    M4a,
+    Flac,
+    Original,
 }
 
-pub fn download_youtube_audio(
-    video_id: String,
-    title: String,
+impl AudioFormat {
+    pub const ALL: [AudioFormat; 5] = [
+        AudioFormat::Mp3,
+        AudioFormat::Opus,
+        AudioFormat::M4a,
+        AudioFormat::Flac,
+        AudioFormat::Original,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "MP3",
+            AudioFormat::Opus => "Opus",
+            AudioFormat::M4a => "M4A",
+            AudioFormat::Flac => "FLAC",
+            AudioFormat::Original => "Keep original",
+        }
+    }
+
+    // Name understood by yt-dlp's --audio-format, None keeps the source codec
+    fn yt_dlp_name(&self) -> Option<&'static str> {
+        match self {
+            AudioFormat::Mp3 => Some("mp3"),
+            AudioFormat::Opus => Some("opus"),
+            AudioFormat::M4a => Some("m4a"),
+            AudioFormat::Flac => Some("flac"),
+            AudioFormat::Original => None,
+        }
+    }
+
+    /// The name `from_name` reads back
+    pub fn name(&self) -> &'static str {
+        self.yt_dlp_name().unwrap_or("original")
+    }
+
+    pub fn from_name(name: &str) -> Option<AudioFormat> {
+        match name.to_lowercase().as_str() {
+            "mp3" => Some(AudioFormat::Mp3),
+            "opus" => Some(AudioFormat::Opus),
+            "m4a" => Some(AudioFormat::M4a),
+            "flac" => Some(AudioFormat::Flac),
+            "original" | "best" => Some(AudioFormat::Original),
+            _ => None,
+        }
+    }
+
+    // Lossless and untouched audio have no bitrate to pick
+    pub fn has_quality(&self) -> bool {
+        !matches!(self, AudioFormat::Flac | AudioFormat::Original)
+    }
+
+    pub fn next(&self) -> AudioFormat {
+        let index = Self::ALL.iter().position(|f| f == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioQuality {
+    Best,
+    Kbps320,
+    Kbps256,
+    Kbps192,
+    Kbps128,
+}
+
+impl AudioQuality {
+    pub const ALL: [AudioQuality; 5] = [
+        AudioQuality::Best,
+        AudioQuality::Kbps320,
+        AudioQuality::Kbps256,
+        AudioQuality::Kbps192,
+        AudioQuality::Kbps128,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AudioQuality::Best => "Best (VBR 0)",
+            AudioQuality::Kbps320 => "320 kbps",
+            AudioQuality::Kbps256 => "256 kbps",
+            AudioQuality::Kbps192 => "192 kbps",
+            AudioQuality::Kbps128 => "128 kbps",
+        }
+    }
+
+    /// The name `from_name` reads back
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioQuality::Best => "best",
+            AudioQuality::Kbps320 => "320k",
+            AudioQuality::Kbps256 => "256k",
+            AudioQuality::Kbps192 => "192k",
+            AudioQuality::Kbps128 => "128k",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<AudioQuality> {
+        match name.to_lowercase().trim_end_matches('k') {
+            "best" | "0" => Some(AudioQuality::Best),
+            "320" => Some(AudioQuality::Kbps320),
+            "256" => Some(AudioQuality::Kbps256),
+            "192" => Some(AudioQuality::Kbps192),
+            "128" => Some(AudioQuality::Kbps128),
+            _ => None,
+        }
+    }
+
+    // Value for yt-dlp's --audio-quality
+    fn yt_dlp_value(&self) -> &'static str {
+        match self {
+            AudioQuality::Best => "0",
+            AudioQuality::Kbps320 => "320K",
+            AudioQuality::Kbps256 => "256K",
+            AudioQuality::Kbps192 => "192K",
+            AudioQuality::Kbps128 => "128K",
+        }
+    }
+
+    pub fn next(&self) -> AudioQuality {
+        let index = Self::ALL.iter().position(|q| q == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DownloadOptions {
+    pub format: AudioFormat,
+    pub quality: AudioQuality,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            format: AudioFormat::Mp3,
+            quality: AudioQuality::Best,
+        }
+    }
+}
+
+impl DownloadOptions {
+    fn yt_dlp_args(&self) -> Vec<&'static str> {
+        let mut args = vec!["--extract-audio", "-f", "bestaudio"];
+        if let Some(format) = self.format.yt_dlp_name() {
+            args.extend(["--audio-format", format]);
+            if self.format.has_quality() {
+                args.extend(["--audio-quality", self.quality.yt_dlp_value()]);
+            }
+        }
+        args
+    }
+}
+
+/// Where a finished download should go according to the configured template
+/// and collision policy, None when an existing file means it should be skipped
+pub fn target_path(config: &Config, metadata: &TrackMetadata, extension: &str) -> Option<PathBuf> {
+    let relative = render_template(&config.filename_template, metadata, extension);
+    resolve_collision(&config.download_dir.join(relative), config.on_collision)
+}
+
+/// How a single download ended when it did not fail
+pub enum Downloaded {
+    // Carries the tagging error, if tagging failed
+    Saved(Option<String>),
+    // The target file exists and the collision policy says skip
+    Skipped,
+    // Another item already gave us a file with the same checksum
+    Duplicate(PathBuf),
+}
+
+/// Downloads one track on a background thread, reporting through `download_status`
+pub fn download_track(
+    source: Arc<dyn MusicSource>,
+    result: SearchResult,
+    options: DownloadOptions,
+    config: Config,
+    history: Arc<Mutex<DownloadHistory>>,
     download_status: Arc<Mutex<Option<String>>>
 ) {
-    let status_message = format!("{} is downloading", title);
+    let title = result.title.clone();
+    let status_message = if source.has_download_options(&result) {
+        format!("{} is downloading ({})", title, options.format.label())
+    } else {
+        format!("{} is downloading", title)
+    };
     {
         let mut status = download_status.lock().unwrap();
         *status = Some(status_message);
     }
 
     thread::spawn(move || {
-        let download_path = get_download_path();
-        if let Err(e) = fs::create_dir_all(&download_path) {
-            let mut status_message = download_status.lock().unwrap();
-            *status_message = Some(format!("Failed to create directory: {}", e));
-            return;
-        }
-
-        let sanitized_title = title.replace("/", "_").replace("\\", "_");
-        let output_path = download_path.join(format!("{} (PJ-PLAYER).mp3", sanitized_title));
-
-        let status = Command::new(YT_DLP_PATH)
-            .args(
-                &[
-                    "--extract-audio",
-                    "--audio-format",
-                    "mp3",
-                    "-o",
-                    output_path.to_str().unwrap(),
-                    &format!("https://www.youtube.com/watch?v={}", video_id),
-                ]
-            )
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status();
-
+        let message = outcome_message(&title, source.download(&result, options, &config, &history, None));
         let mut status_message = download_status.lock().unwrap();
-        *status_message = match status {
-            Ok(status) if status.success() => Some(format!("{} downloaded successfully", title)),
-            Ok(status) => Some(format!("yt-dlp returned an error: Exit code {}", status)),
-            Err(err) => Some(format!("Error executing yt-dlp: {}", err)),
-        };
+        *status_message = Some(message);
     });
 }
 
-pub fn download_archive_audio(
-    identifier: String,
-    title: String,
+/// How a download of `title` ended, in words
+pub fn outcome_message(title: &str, outcome: SourceResult<Downloaded>) -> String {
+    match outcome {
+        Ok(Downloaded::Saved(None)) => format!("{} downloaded successfully", title),
+        Ok(Downloaded::Saved(Some(e))) => format!("{} downloaded, tagging failed: {}", title, e),
+        Ok(Downloaded::Skipped) => format!("{} already exists, skipped", title),
+        Ok(Downloaded::Duplicate(path)) => format!("Same file was already downloaded as {}", path.display()),
+        Err(e) => format!("Download failed: {}", e),
+    }
+}
+
+/// Downloads the selected playlist entries one after another into a folder
+/// named after the playlist. Entries already in the history are left alone.
+pub fn download_playlist(
+    source: Arc<dyn MusicSource>,
+    playlist_title: String,
+    entries: Vec<(u32, SearchResult)>,
+    options: DownloadOptions,
+    config: Config,
+    history: Arc<Mutex<DownloadHistory>>,
     download_status: Arc<Mutex<Option<String>>>
 ) {
-    let status_message = format!("{} is downloading", title);
     {
         let mut status = download_status.lock().unwrap();
-        *status = Some(status_message);
+        *status = Some(format!("{}: queued {} downloads", playlist_title, entries.len()));
     }
 
     thread::spawn(move || {
-        let download_path = get_download_path();
-        if let Err(e) = fs::create_dir_all(&download_path) {
-            let mut status_message = download_status.lock().unwrap();
-            *status_message = Some(format!("Failed to create directory: {}", e));
-            return;
-        }
+        let mut playlist_config = config.clone();
+        playlist_config.download_dir = config.download_dir.join(
+            sanitize_component(&playlist_title, false)
+        );
 
-        let sanitized_title = title.replace("/", "_").replace("\\", "_");
-        let output_path = download_path.join(format!("{} (PJ-PLAYER).mp3", sanitized_title));
-        let client = reqwest::blocking::Client::new();
-
-        // More robust error handling
-        match download_archive_file(&client, &identifier, &output_path) {
-            Ok(_) => {
-                let mut status_message = download_status.lock().unwrap();
-                *status_message = Some(format!("{} downloaded successfully", title));
+        let total = entries.len();
+        let (mut finished, mut skipped, mut failed) = (0, 0, 0);
+        for (n, (position, result)) in entries.iter().enumerate() {
+            if history.lock().unwrap().find(&result.source, &result.identifier).is_some() {
+                skipped += 1;
+                continue;
+            }
+            {
+                let mut status = download_status.lock().unwrap();
+                *status = Some(format!("{}/{}: {} is downloading", n + 1, total, result.title));
             }
-            Err(e) => {
-                let mut status_message = download_status.lock().unwrap();
-                *status_message = Some(format!("Download failed: {}", e));
+            let playlist = Some((playlist_title.as_str(), *position));
+            match source.download(result, options, &playlist_config, &history, playlist) {
+                Ok(Downloaded::Saved(_)) => {
+                    finished += 1;
+                }
+                Ok(Downloaded::Skipped) | Ok(Downloaded::Duplicate(_)) => {
+                    skipped += 1;
+                }
+                Err(_) => {
+                    failed += 1;
+                }
             }
         }
+
+        let mut status = download_status.lock().unwrap();
+        *status = Some(
+            format!(
+                "{}: {} downloaded, {} skipped, {} failed",
+                playlist_title,
+                finished,
+                skipped,
+                failed
+            )
+        );
     });
 }
 
-// Separate function for download logic with better error handling
-fn download_archive_file(
-    client: &reqwest::blocking::Client,
-    identifier: &str,
-    output_path: &PathBuf
-) -> Result<(), Box<dyn std::error::Error>> {
+/// Downloads `location` with yt-dlp, for every site yt-dlp handles. Blocking.
+pub fn fetch_with_yt_dlp(
+    result: &SearchResult,
+    location: &str,
+    options: DownloadOptions,
+    config: &Config,
+    history: &Mutex<DownloadHistory>,
+    playlist: Option<(&str, u32)>
+) -> SourceResult<Downloaded> {
+    let download_path = config.download_dir.clone();
+    fs::create_dir_all(&download_path).map_err(|e| format!("Failed to create directory: {}", e))?;
+
+    // yt-dlp writes to a temporary name first, the final name depends on
+    // tags and on the extension it picks for the chosen format
+    let output_path = download_path.join(format!(".pjplayer-{}.%(ext)s", temp_name(result)));
+
+    let mut command = Command::new(YT_DLP_PATH);
+    command.args(options.yt_dlp_args());
+    if options.format != AudioFormat::Original {
+        command.args(["--embed-thumbnail", "--convert-thumbnails", "jpg"]);
+    }
+    // Print the final path and the fields we tag with once the file is in place
+    let output = command
+        .args(
+            [
+                "--no-simulate",
+                "--print",
+                "after_move:filepath",
+                "--print",
+                "after_move:%(.{title,artist,album,track_number,release_year,upload_date,uploader,webpage_url})j",
+                "-o",
+                output_path.to_str().unwrap(),
+                location,
+            ]
+        )
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("Error executing yt-dlp: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("yt-dlp returned an error: Exit code {}", output.status).into());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    let temp_path = lines
+        .next()
+        .map(PathBuf::from)
+        .ok_or("yt-dlp did not report the downloaded file")?;
+    let info: Value = lines
+        .next()
+        .and_then(|line| serde_json::from_str(line).ok())
+        .unwrap_or(Value::Null);
+
+    let mut metadata = youtube_metadata(result, location, &info);
+    if let Some((playlist_title, position)) = playlist {
+        metadata.album.get_or_insert_with(|| playlist_title.to_string());
+        metadata.track.get_or_insert(position);
+    }
+
+    let extension = temp_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("mp3")
+        .to_string();
+    let Some(final_path) = target_path(config, &metadata, &extension) else {
+        let _ = fs::remove_file(&temp_path);
+        return Ok(Downloaded::Skipped);
+    };
+    if let Some(parent) = final_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+    }
+    fs::rename(&temp_path, &final_path).map_err(|e|
+        format!("Failed to move download into place: {}", e)
+    )?;
+    history.lock().unwrap().record(&result.source, &result.identifier, &final_path, None);
+
+    let tag_error = write_tags(&final_path, &metadata)
+        .err()
+        .map(|e| e.to_string());
+    Ok(Downloaded::Saved(tag_error))
+}
+
+// Identifiers of pasted links are whole URLs, so temporary files are named
+// after a hash instead
+fn temp_name(result: &SearchResult) -> String {
+    let mut hasher = DefaultHasher::new();
+    result.identifier.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Downloads a URL that points straight at an audio file. Blocking.
+pub fn fetch_direct(
+    result: &SearchResult,
+    url: &str,
+    config: &Config,
+    history: &Mutex<DownloadHistory>
+) -> SourceResult<Downloaded> {
+    let file_name = Path::new(url.split(['?', '#']).next().unwrap_or(""))
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = Path::new(&file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("mp3")
+        .to_lowercase();
+    // Pasted links are titled after the file, anything else brings a real title
+    let title = if result.title == file_name || result.title == url {
+        Path::new(&file_name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| result.title.clone())
+    } else {
+        result.title.clone()
+    };
+    let metadata = TrackMetadata {
+        title,
+        artist: result.artist.clone(),
+        year: result.year.clone(),
+        source_url: Some(url.to_string()),
+        ..TrackMetadata::default()
+    };
+    let Some(output_path) = target_path(config, &metadata, &extension) else {
+        return Ok(Downloaded::Skipped);
+    };
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let client = reqwest::blocking::Client::new();
+    let response = client.get(url).send()?.error_for_status()?;
+    save_response(response, &output_path, None)?;
+    history.lock().unwrap().record(&result.source, &result.identifier, &output_path, None);
+
+    let tag_error = write_tags(&output_path, &metadata)
+        .err()
+        .map(|e| e.to_string());
+    Ok(Downloaded::Saved(tag_error))
+}
+
+/// Saves a copy of a file fetched while streaming (the audio cache's copy of
+/// the track) to the download folder, tagged and recorded like a download
+pub fn keep_file(
+    result: &SearchResult,
+    location: &str,
+    file: &Path,
+    config: &Config,
+    history: &Mutex<DownloadHistory>
+) -> SourceResult<Downloaded> {
+    let extension = probe_extension(file).ok_or("Unknown audio format")?;
+    let metadata = TrackMetadata {
+        title: result.title.clone(),
+        artist: result.artist.clone(),
+        year: result.year.clone(),
+        source_url: Some(location.to_string()),
+        ..TrackMetadata::default()
+    };
+    let Some(output_path) = target_path(config, &metadata, extension) else {
+        return Ok(Downloaded::Skipped);
+    };
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(file, &output_path)?;
+    history.lock().unwrap().record(&result.source, &result.identifier, &output_path, None);
+
+    let tag_error = write_tags(&output_path, &metadata)
+        .err()
+        .map(|e| e.to_string());
+    Ok(Downloaded::Saved(tag_error))
+}
+
+// Cached audio has no extension, ffprobe tells the container
+fn probe_extension(file: &Path) -> Option<&'static str> {
+    let output = Command::new(FFPROBE_PATH)
+        .args(["-v", "quiet", "-show_entries", "format=format_name", "-of", "default=nw=1:nk=1"])
+        .arg(file)
+        .output()
+        .ok()?;
+    let format_name = String::from_utf8_lossy(&output.stdout);
+    let extension = match format_name.trim().split(',').next()? {
+        "mp3" => "mp3",
+        "ogg" => "ogg",
+        "flac" => "flac",
+        "wav" => "wav",
+        "aac" => "aac",
+        "matroska" => "webm",
+        "mov" => "m4a",
+        _ => {
+            return None;
+        }
+    };
+    Some(extension)
+}
+
+// Search metadata first, gaps filled from what yt-dlp extracted for the video
+fn youtube_metadata(result: &SearchResult, location: &str, info: &Value) -> TrackMetadata {
+    let year = archive_field(&info["release_year"]).or_else(||
+        info["upload_date"].as_str().map(|date| date.chars().take(4).collect())
+    );
+    // Pasted links and some listings only carry the URL as a title until
+    // yt-dlp has looked at them
+    let title = match info["title"].as_str() {
+        Some(title) if result.title == location => title.to_string(),
+        _ => result.title.clone(),
+    };
+    TrackMetadata {
+        title,
+        artist: info["artist"]
+            .as_str()
+            .map(String::from)
+            .or_else(|| result.artist.clone())
+            .or_else(|| info["uploader"].as_str().map(String::from)),
+        album: info["album"].as_str().map(String::from),
+        year,
+        track: info["track_number"].as_u64().map(|n| n as u32),
+        source_url: info["webpage_url"]
+            .as_str()
+            .map(String::from)
+            .or_else(|| Some(location.to_string())),
+        cover_url: None,
+    }
+}
+
+/// Downloads the best audio file of an Archive item. Blocking.
+pub fn fetch_archive(
+    result: &SearchResult,
+    config: &Config,
+    history: &Mutex<DownloadHistory>
+) -> SourceResult<Downloaded> {
+    let client = reqwest::blocking::Client::new();
+    let identifier = &result.identifier;
     let metadata_url = format!("https://archive.org/metadata/{}", identifier);
     let metadata_response = client.get(&metadata_url).send()?;
     let metadata: Value = metadata_response.json()?;
@@ -121,11 +544,44 @@ fn download_archive_file(
                         download_name
                     );
 
-                    let mut response = client.get(&download_url).send()?;
-                    let mut file = File::create(output_path)?;
-                    std::io::copy(&mut response, &mut file)?;
+                    // Re-downloading the same item was confirmed in the UI, so only
+                    // files reached through a different item count as duplicates
+                    let checksum = file["md5"].as_str().map(String::from);
+                    if let Some(checksum) = &checksum {
+                        let history = history.lock().unwrap();
+                        if let Some(entry) = history.find_checksum(checksum) {
+                            if &entry.identifier != identifier {
+                                return Ok(Downloaded::Duplicate(entry.path.clone()));
+                            }
+                        }
+                    }
+
+                    // Keep the real extension, Archive items are not always MP3
+                    let extension = Path::new(download_name)
+                        .extension()
+                        .and_then(|e| e.to_str())
+                        .unwrap_or("mp3")
+                        .to_lowercase();
+                    let track_metadata = archive_metadata(result, &metadata["metadata"], file);
+                    let Some(output_path) = target_path(config, &track_metadata, &extension) else {
+                        return Ok(Downloaded::Skipped);
+                    };
+                    if let Some(parent) = output_path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+
+                    let response = client.get(&download_url).send()?.error_for_status()?;
+                    let size = archive_field(&file["size"]).and_then(|size| size.parse().ok());
+                    save_response(response, &output_path, size)?;
+                    history
+                        .lock()
+                        .unwrap()
+                        .record(&result.source, identifier, &output_path, checksum);
 
-                    return Ok(());
+                    let tag_error = write_tags(&output_path, &track_metadata)
+                        .err()
+                        .map(|e| e.to_string());
+                    return Ok(Downloaded::Saved(tag_error));
                 }
             }
         }
@@ -133,3 +589,47 @@ fn download_archive_file(
 
     Err("No suitable audio file found".into())
 }
+
+// Writes the body to a hidden file next to `path` and puts it in place only
+// once all of it arrived (and has the size the server announced, when there
+// is one), so a failed download never replaces a good file
+fn save_response(mut response: reqwest::blocking::Response, path: &Path, size: Option<u64>) -> SourceResult<()> {
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or("Invalid file name")?;
+    let partial_path = path.with_file_name(format!(".downloading-{}-{}", unique_suffix(), file_name));
+    let written = File::create(&partial_path).and_then(|mut file| std::io::copy(&mut response, &mut file));
+    let outcome: SourceResult<()> = match (written, size) {
+        (Ok(written), Some(size)) if written != size =>
+            Err(format!("Download incomplete, got {} of {} bytes", written, size).into()),
+        (Ok(_), _) => fs::rename(&partial_path, path).map_err(|e| e.into()),
+        (Err(e), _) => Err(e.into()),
+    };
+    if outcome.is_err() {
+        let _ = fs::remove_file(&partial_path);
+    }
+    outcome
+}
+
+// File-level tags win over item-level ones, the item itself acts as the album
+fn archive_metadata(result: &SearchResult, item: &Value, file: &Value) -> TrackMetadata {
+    let year = archive_field(&item["year"])
+        .or_else(|| archive_field(&item["date"]).map(|date| date.chars().take(4).collect()))
+        .or_else(|| result.year.clone());
+    TrackMetadata {
+        title: archive_field(&file["title"]).unwrap_or_else(|| result.title.clone()),
+        artist: archive_field(&file["artist"])
+            .or_else(|| archive_field(&file["creator"]))
+            .or_else(|| archive_field(&item["creator"]))
+            .or_else(|| result.artist.clone()),
+        album: archive_field(&file["album"]).or_else(|| archive_field(&item["title"])),
+        year,
+        // Track numbers show up as "3" or "3/12"
+        track: archive_field(&file["track"]).and_then(|track| {
+            track.split('/').next().and_then(|n| n.trim().parse().ok())
+        }),
+        source_url: Some(format!("https://archive.org/details/{}", result.identifier)),
+        cover_url: Some(format!("https://archive.org/services/img/{}", result.identifier)),
+    }
+}
@@ -0,0 +1,121 @@
+// history.rs
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::time::{ SystemTime, UNIX_EPOCH };
+use serde_json::{ json, Value };
+use crate::config::data_dir;
+
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    // Key of the source the file came from
+    pub source: String,
+    pub identifier: String,
+    pub path: PathBuf,
+    // MD5 published by the Archive for the downloaded file
+    pub checksum: Option<String>,
+    pub downloaded_at: u64,
+}
+
+/// Every finished download, persisted as `history.json` in the data directory
+#[derive(Debug, Default)]
+pub struct DownloadHistory {
+    entries: Vec<HistoryEntry>,
+}
+
+fn history_path() -> PathBuf {
+    data_dir().join("history.json")
+}
+
+impl DownloadHistory {
+    pub fn load() -> DownloadHistory {
+        let json = fs::read_to_string(history_path())
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+            .unwrap_or(Value::Null);
+
+        let entries = json
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| {
+                        Some(HistoryEntry {
+                            source: item["source"].as_str()?.to_string(),
+                            identifier: item["identifier"].as_str()?.to_string(),
+                            path: PathBuf::from(item["path"].as_str()?),
+                            checksum: item["checksum"].as_str().map(String::from),
+                            downloaded_at: item["downloaded_at"].as_u64().unwrap_or(0),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        DownloadHistory { entries }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let items: Vec<Value> = self.entries
+            .iter()
+            .map(|entry| {
+                json!({
+                    "source": entry.source,
+                    "identifier": entry.identifier,
+                    "path": entry.path.to_string_lossy(),
+                    "checksum": entry.checksum,
+                    "downloaded_at": entry.downloaded_at,
+                })
+            })
+            .collect();
+        let path = history_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&Value::Array(items))?)
+    }
+
+    /// Remembers a finished download and writes the history to disk. An older
+    /// entry for the same file is replaced. The file is read again first, the
+    /// TUI, the daemon and `pjplayer download` may all have added to it.
+    pub fn record(&mut self, source: &str, identifier: &str, path: &Path, checksum: Option<String>) {
+        self.entries = DownloadHistory::load().entries;
+        self.entries.retain(|entry| entry.path != path);
+        self.entries.push(HistoryEntry {
+            source: source.to_string(),
+            identifier: identifier.to_string(),
+            path: path.to_path_buf(),
+            checksum,
+            downloaded_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        });
+        let _ = self.save();
+    }
+
+    /// The most recent download of this item whose file is still on disk
+    pub fn find(&self, source: &str, identifier: &str) -> Option<&HistoryEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| {
+                entry.source == source && entry.identifier == identifier && entry.path.exists()
+            })
+    }
+
+    /// Every downloaded file that is still on disk
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .map(|entry| entry.path.clone())
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    pub fn find_checksum(&self, checksum: &str) -> Option<&HistoryEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.checksum.as_deref() == Some(checksum) && entry.path.exists())
+    }
+}
@@ -0,0 +1,648 @@
+// library.rs
+use std::collections::{ HashMap, HashSet };
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::process::Command;
+use std::sync::mpsc::{ self, RecvTimeoutError };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::{ Duration, UNIX_EPOCH };
+use notify::{ EventKind, RecommendedWatcher, RecursiveMode, Watcher };
+use serde_json::{ json, Value };
+use crate::app::SearchResult;
+use crate::sources::LOCAL;
+use crate::config::data_dir;
+use crate::search::archive_field;
+
+const FFPROBE_PATH: &str = "ffprobe";
+
+pub const AUDIO_EXTENSIONS: [&str; 12] = [
+    "mp3", "flac", "ogg", "oga", "opus", "m4a", "aac", "wav", "wma", "aiff", "webm", "mka",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct LibraryTrack {
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<String>,
+    pub track: Option<u32>,
+    pub duration: Option<u64>,
+    // Modification time and size when the file was indexed
+    pub mtime: u64,
+    pub size: u64,
+}
+
+impl LibraryTrack {
+    pub fn to_search_result(&self) -> SearchResult {
+        SearchResult {
+            identifier: self.path.to_string_lossy().to_string(),
+            title: self.title.clone(),
+            artist: self.artist.clone(),
+            year: self.year.clone(),
+            duration: self.duration,
+            source: LOCAL.to_string(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "path": self.path.to_string_lossy(),
+            "title": self.title,
+            "artist": self.artist,
+            "album": self.album,
+            "genre": self.genre,
+            "year": self.year,
+            "track": self.track,
+            "duration": self.duration,
+            "mtime": self.mtime,
+            "size": self.size,
+        })
+    }
+
+    fn from_json(item: &Value) -> Option<LibraryTrack> {
+        Some(LibraryTrack {
+            path: PathBuf::from(item["path"].as_str()?),
+            title: item["title"].as_str()?.to_string(),
+            artist: item["artist"].as_str().map(String::from),
+            album: item["album"].as_str().map(String::from),
+            genre: item["genre"].as_str().map(String::from),
+            year: item["year"].as_str().map(String::from),
+            track: item["track"].as_u64().map(|n| n as u32),
+            duration: item["duration"].as_u64(),
+            mtime: item["mtime"].as_u64().unwrap_or(0),
+            size: item["size"].as_u64().unwrap_or(0),
+        })
+    }
+
+    fn matches(&self, words: &[String]) -> bool {
+        let haystack = [
+            Some(&self.title),
+            self.artist.as_ref(),
+            self.album.as_ref(),
+            self.genre.as_ref(),
+        ]
+            .into_iter()
+            .flatten()
+            .map(|field| field.to_lowercase())
+            .collect::<Vec<_>>()
+            .join(" ");
+        words.iter().all(|word| haystack.contains(word.as_str()))
+    }
+}
+
+/// Index of the audio files found in the configured library folders,
+/// persisted as `library.json` in the data directory
+#[derive(Debug, Default)]
+pub struct Library {
+    pub tracks: Vec<LibraryTrack>,
+}
+
+fn library_path() -> PathBuf {
+    data_dir().join("library.json")
+}
+
+impl Library {
+    pub fn load() -> Library {
+        let json = fs::read_to_string(library_path())
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+            .unwrap_or(Value::Null);
+        let tracks = json["tracks"]
+            .as_array()
+            .map(|items| items.iter().filter_map(LibraryTrack::from_json).collect())
+            .unwrap_or_default();
+        Library { tracks }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let tracks: Vec<Value> = self.tracks.iter().map(LibraryTrack::to_json).collect();
+        let path = library_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(&json!({ "tracks": tracks }))?)
+    }
+
+    /// Walks every folder and returns the new index. Files whose modification
+    /// time and size match the current index are reused as they are, only new
+    /// or changed files are probed, and files that are gone are dropped.
+    pub fn rescan(&self, dirs: &[PathBuf]) -> Library {
+        let known: HashMap<&Path, &LibraryTrack> = self.tracks
+            .iter()
+            .map(|track| (track.path.as_path(), track))
+            .collect();
+
+        let mut files = Vec::new();
+        for dir in dirs {
+            collect_audio_files(dir, &mut files);
+        }
+        let tracks = files
+            .iter()
+            .filter_map(|path| {
+                match (known.get(path.as_path()), file_stamp(path)) {
+                    (Some(track), Some((mtime, size))) if track.mtime == mtime && track.size == size =>
+                        Some((*track).clone()),
+                    _ => probe_track(path),
+                }
+            })
+            .collect();
+        Library { tracks }
+    }
+
+    /// Folds the result of a `rescan` that started from `before` into the live
+    /// index. Entries the watcher added, changed or dropped while the scan ran
+    /// are newer than what the scan saw, so those are left as they are.
+    pub fn merge_scan(&mut self, before: &Library, scanned: Library) {
+        let stamp = |track: &LibraryTrack| (track.mtime, track.size);
+        let before: HashMap<&Path, (u64, u64)> = before.tracks
+            .iter()
+            .map(|track| (track.path.as_path(), stamp(track)))
+            .collect();
+        let untouched = |track: &LibraryTrack| before.get(track.path.as_path()) == Some(&stamp(track));
+
+        let scanned_paths: HashSet<PathBuf> = scanned.tracks
+            .iter()
+            .map(|track| track.path.clone())
+            .collect();
+        self.tracks.retain(|track| scanned_paths.contains(&track.path) || !untouched(track));
+
+        let mut live: HashMap<PathBuf, usize> = self.tracks
+            .iter()
+            .enumerate()
+            .map(|(index, track)| (track.path.clone(), index))
+            .collect();
+        for track in scanned.tracks {
+            match live.get(&track.path) {
+                Some(&index) => {
+                    if untouched(&self.tracks[index]) {
+                        self.tracks[index] = track;
+                    }
+                }
+                // Gone from the live index since the scan started
+                None if before.contains_key(track.path.as_path()) => {}
+                None => {
+                    live.insert(track.path.clone(), self.tracks.len());
+                    self.tracks.push(track);
+                }
+            }
+        }
+    }
+
+    /// Files under a changed path that need probing before the index can be
+    /// brought in line with it: new audio files and ones that changed since
+    /// they were indexed.
+    pub fn stale_files(&self, path: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        if path.is_dir() {
+            collect_audio_files(path, &mut files);
+        } else if path.is_file() && is_audio_file(path) && !is_hidden(path) {
+            files.push(path.to_path_buf());
+        }
+        files.retain(|file| {
+            let indexed = self.tracks.iter().find(|track| &track.path == file);
+            match (indexed, file_stamp(file)) {
+                (Some(track), Some((mtime, size))) => track.mtime != mtime || track.size != size,
+                _ => true,
+            }
+        });
+        files
+    }
+
+    /// Drops entries for a path that no longer exists, along with everything
+    /// below it when it was a folder. Returns true when the index changed.
+    pub fn remove_missing(&mut self, path: &Path) -> bool {
+        if path.exists() {
+            return false;
+        }
+        let before = self.tracks.len();
+        self.tracks.retain(|track| !track.path.starts_with(path));
+        self.tracks.len() != before
+    }
+
+    /// Stores what probing `path` gave: the new entry, or no entry at all for
+    /// files ffprobe cannot read. Returns true when the index changed.
+    pub fn update(&mut self, path: &Path, probed: Option<LibraryTrack>) -> bool {
+        let position = self.tracks.iter().position(|track| track.path == path);
+        match (position, probed) {
+            (Some(index), Some(track)) => {
+                self.tracks[index] = track;
+            }
+            (None, Some(track)) => {
+                self.tracks.push(track);
+            }
+            (Some(index), None) => {
+                self.tracks.remove(index);
+            }
+            (None, None) => {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Every word of the query has to appear in the title, artist, album or genre
+    pub fn search(&self, query: &str) -> Vec<SearchResult> {
+        let words: Vec<String> = query.split_whitespace().map(|w| w.to_lowercase()).collect();
+        self.tracks
+            .iter()
+            .filter(|track| track.matches(&words))
+            .map(LibraryTrack::to_search_result)
+            .collect()
+    }
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| AUDIO_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+// Hidden entries are skipped, which also leaves out our own temporary files
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy().starts_with('.'))
+        .unwrap_or(false)
+}
+
+pub fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    Some((mtime, metadata.len()))
+}
+
+fn collect_audio_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if is_hidden(&path) {
+            continue;
+        }
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => collect_audio_files(&path, files),
+            Ok(file_type) if file_type.is_file() && is_audio_file(&path) => files.push(path),
+            _ => {}
+        }
+    }
+}
+
+/// Reads tags and duration with ffprobe. Files ffprobe cannot read are left out.
+pub fn probe_track(path: &Path) -> Option<LibraryTrack> {
+    let (mtime, size) = file_stamp(path)?;
+    let output = Command::new(FFPROBE_PATH)
+        .args(["-v", "quiet", "-print_format", "json", "-show_format"])
+        .arg(path)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let json: Value = serde_json::from_slice(&output.stdout).ok()?;
+    let format = &json["format"];
+
+    // Tag names differ in case between containers (TITLE, title, Title)
+    let tags: Vec<(String, &Value)> = format["tags"]
+        .as_object()
+        .map(|tags| tags.iter().map(|(k, v)| (k.to_lowercase(), v)).collect())
+        .unwrap_or_default();
+    let tag = |name: &str| {
+        tags.iter()
+            .find(|(key, _)| key == name)
+            .and_then(|(_, value)| archive_field(value))
+            .filter(|value| !value.trim().is_empty())
+    };
+
+    let fallback_title = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    Some(LibraryTrack {
+        path: path.to_path_buf(),
+        title: tag("title").unwrap_or(fallback_title),
+        artist: tag("artist").or_else(|| tag("album_artist")),
+        album: tag("album"),
+        genre: tag("genre"),
+        year: tag("date")
+            .or_else(|| tag("year"))
+            .map(|date| date.chars().take(4).collect()),
+        track: tag("track").and_then(|track| {
+            track.split('/').next().and_then(|n| n.trim().parse().ok())
+        }),
+        duration: format["duration"]
+            .as_str()
+            .and_then(|d| d.parse::<f64>().ok())
+            .map(|d| d.round() as u64),
+        mtime,
+        size,
+    })
+}
+
+// Files being written fire many events, wait for things to settle before probing
+const WATCH_SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// Watches the library folders (inotify on Linux) and keeps the index up to
+/// date while the app runs, including files pjplayer itself downloads. The
+/// returned watcher has to be kept alive for as long as watching should go on.
+/// Folders that could not be watched (a missing one, say) come back with the
+/// reason.
+pub fn watch_library(
+    dirs: &[PathBuf],
+    library: Arc<Mutex<Library>>
+) -> (Option<RecommendedWatcher>, Vec<(PathBuf, String)>) {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = match notify::recommended_watcher(sender) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            return (None, dirs.iter().map(|dir| (dir.clone(), e.to_string())).collect());
+        }
+    };
+    let unwatched = dirs
+        .iter()
+        .filter_map(|dir| watcher.watch(dir, RecursiveMode::Recursive).err().map(|e| (dir.clone(), e.to_string())))
+        .collect();
+
+    thread::spawn(move || {
+        let mut pending: HashSet<PathBuf> = HashSet::new();
+        loop {
+            match receiver.recv_timeout(WATCH_SETTLE_TIME) {
+                Ok(Ok(event)) => {
+                    if !matches!(event.kind, EventKind::Access(_)) {
+                        pending.extend(event.paths);
+                    }
+                }
+                Ok(Err(_)) => {}
+                Err(RecvTimeoutError::Timeout) => {
+                    if pending.is_empty() {
+                        continue;
+                    }
+                    // ffprobe runs without the lock held, so searches and
+                    // browsing are not held up by a big copy into the library
+                    let mut stale = Vec::new();
+                    let mut changed = false;
+                    {
+                        let mut library = library.lock().unwrap();
+                        for path in pending.drain() {
+                            changed |= library.remove_missing(&path);
+                            stale.extend(library.stale_files(&path));
+                        }
+                    }
+                    let probed: Vec<(PathBuf, Option<LibraryTrack>)> = stale
+                        .into_iter()
+                        .map(|path| {
+                            let track = probe_track(&path);
+                            (path, track)
+                        })
+                        .collect();
+                    let mut library = library.lock().unwrap();
+                    for (path, track) in probed {
+                        changed |= library.update(&path, track);
+                    }
+                    if changed {
+                        let _ = library.save();
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    break;
+                }
+            }
+        }
+    });
+
+    (Some(watcher), unwatched)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Facet {
+    Artist,
+    Album,
+    Genre,
+    Year,
+}
+
+impl Facet {
+    pub const ALL: [Facet; 4] = [Facet::Artist, Facet::Album, Facet::Genre, Facet::Year];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Facet::Artist => "Artists",
+            Facet::Album => "Albums",
+            Facet::Genre => "Genres",
+            Facet::Year => "Years",
+        }
+    }
+
+    /// What to list after picking a value: artist → album → tracks, genre →
+    /// artist, year → album. None means the tracks themselves.
+    pub fn drill_down(&self) -> Option<Facet> {
+        match self {
+            Facet::Artist => Some(Facet::Album),
+            Facet::Album => None,
+            Facet::Genre => Some(Facet::Artist),
+            Facet::Year => Some(Facet::Album),
+        }
+    }
+
+    pub fn value(&self, track: &LibraryTrack) -> String {
+        let value = match self {
+            Facet::Artist => track.artist.clone(),
+            Facet::Album => track.album.clone(),
+            Facet::Genre => track.genre.clone(),
+            Facet::Year => track.year.clone(),
+        };
+        value.unwrap_or_else(|| "Unknown".to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrowseSort {
+    Name,
+    // Most tracks first for facet values, album order for track lists
+    Tracks,
+    Year,
+}
+
+impl BrowseSort {
+    pub fn next(&self) -> BrowseSort {
+        match self {
+            BrowseSort::Name => BrowseSort::Tracks,
+            BrowseSort::Tracks => BrowseSort::Year,
+            BrowseSort::Year => BrowseSort::Name,
+        }
+    }
+
+    pub fn label(&self, listing_tracks: bool) -> &'static str {
+        match (self, listing_tracks) {
+            (BrowseSort::Name, false) => "Name",
+            (BrowseSort::Name, true) => "Title",
+            (BrowseSort::Tracks, false) => "Track count",
+            (BrowseSort::Tracks, true) => "Track number",
+            (BrowseSort::Year, _) => "Year",
+        }
+    }
+}
+
+impl Library {
+    /// Tracks matching every (facet, value) pair picked so far
+    pub fn filtered_tracks(&self, filters: &[(Facet, String)], sort: BrowseSort) -> Vec<&LibraryTrack> {
+        let mut tracks: Vec<&LibraryTrack> = self.tracks
+            .iter()
+            .filter(|track| filters.iter().all(|(facet, value)| &facet.value(track) == value))
+            .collect();
+        match sort {
+            BrowseSort::Name => tracks.sort_by_key(|t| t.title.to_lowercase()),
+            BrowseSort::Tracks =>
+                tracks.sort_by_key(|t| (
+                    t.album.clone().unwrap_or_default(),
+                    t.track.unwrap_or(u32::MAX),
+                    t.path.clone(),
+                )),
+            BrowseSort::Year =>
+                tracks.sort_by_key(|t| (t.year.clone().unwrap_or_default(), t.title.to_lowercase())),
+        }
+        tracks
+    }
+
+    /// Distinct values of `facet` among the filtered tracks, with their track counts
+    pub fn facet_values(
+        &self,
+        facet: Facet,
+        filters: &[(Facet, String)],
+        sort: BrowseSort
+    ) -> Vec<(String, usize)> {
+        // value -> (track count, earliest year)
+        let mut groups: HashMap<String, (usize, String)> = HashMap::new();
+        for track in self.filtered_tracks(filters, BrowseSort::Name) {
+            let year = track.year.clone().unwrap_or_default();
+            let (count, earliest) = groups.entry(facet.value(track)).or_default();
+            *count += 1;
+            if earliest.is_empty() || (!year.is_empty() && &year < earliest) {
+                *earliest = year;
+            }
+        }
+        let mut values: Vec<(String, usize, String)> = groups
+            .into_iter()
+            .map(|(value, (count, earliest))| (value, count, earliest))
+            .collect();
+        match sort {
+            BrowseSort::Name => values.sort_by(|a, b| a.0.to_lowercase().cmp(&b.0.to_lowercase()).then(a.0.cmp(&b.0))),
+            BrowseSort::Tracks => values.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0))),
+            BrowseSort::Year => values.sort_by(|a, b| a.2.cmp(&b.2).then(a.0.cmp(&b.0))),
+        }
+        values
+            .into_iter()
+            .map(|(value, count, _)| (value, count))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(title: &str, artist: &str, year: Option<&str>) -> LibraryTrack {
+        LibraryTrack {
+            path: PathBuf::from(format!("/music/{}.flac", title)),
+            title: title.to_string(),
+            artist: Some(artist.to_string()),
+            album: None,
+            genre: None,
+            year: year.map(str::to_string),
+            track: None,
+            duration: None,
+            mtime: 0,
+            size: 0,
+        }
+    }
+
+    fn library() -> Library {
+        Library {
+            tracks: vec![
+                track("a", "Beta", Some("2001")),
+                track("b", "alpha", None),
+                track("c", "Beta", Some("1999")),
+                track("d", "Gamma", Some("1990")),
+                track("e", "Beta", None),
+            ],
+        }
+    }
+
+    #[test]
+    fn groups_facet_values_by_name() {
+        let values = library().facet_values(Facet::Artist, &[], BrowseSort::Name);
+        assert_eq!(values, vec![("alpha".to_string(), 1), ("Beta".to_string(), 3), ("Gamma".to_string(), 1)]);
+    }
+
+    #[test]
+    fn sorts_facet_values_by_track_count_and_year() {
+        let names = |sort| {
+            library()
+                .facet_values(Facet::Artist, &[], sort)
+                .into_iter()
+                .map(|(value, _)| value)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(BrowseSort::Tracks), vec!["Beta", "Gamma", "alpha"]);
+        // Values with no year at all sort first
+        assert_eq!(names(BrowseSort::Year), vec!["alpha", "Gamma", "Beta"]);
+    }
+
+    #[test]
+    fn counts_facet_values_within_filters() {
+        let filters = [(Facet::Artist, "Beta".to_string())];
+        let values = library().facet_values(Facet::Year, &filters, BrowseSort::Name);
+        assert_eq!(values, vec![("1999".to_string(), 1), ("2001".to_string(), 1), ("Unknown".to_string(), 1)]);
+    }
+
+    fn titles(library: &Library) -> Vec<String> {
+        let mut titles: Vec<String> = library.tracks
+            .iter()
+            .map(|track| track.title.clone())
+            .collect();
+        titles.sort();
+        titles
+    }
+
+    #[test]
+    fn merges_a_scan_into_the_live_index() {
+        let before = library();
+        let mut live = library();
+        // a: unchanged, b: changed on disk, c: deleted, f: new
+        let mut scanned = Library { tracks: vec![track("a", "Beta", Some("2001")), track("d", "Gamma", Some("1990"))] };
+        let mut changed = track("b", "alpha", None);
+        changed.mtime = 5;
+        scanned.tracks.push(changed);
+        scanned.tracks.push(track("e", "Beta", None));
+        scanned.tracks.push(track("f", "Delta", None));
+
+        live.merge_scan(&before, scanned);
+        assert_eq!(titles(&live), vec!["a", "b", "d", "e", "f"]);
+        assert_eq!(live.tracks.iter().find(|track| track.title == "b").unwrap().mtime, 5);
+    }
+
+    #[test]
+    fn keeps_watcher_changes_made_during_a_scan() {
+        let before = library();
+        let mut live = library();
+        // The watcher updates a, drops d and adds g while the scan runs
+        live.tracks[0].mtime = 9;
+        live.tracks[0].artist = Some("Watched".to_string());
+        live.tracks.retain(|track| track.title != "d");
+        live.tracks.push(track("g", "Omega", None));
+
+        let scanned = Library { tracks: vec![track("a", "Beta", Some("2001")), track("b", "alpha", None), track("d", "Gamma", Some("1990"))] };
+        live.merge_scan(&before, scanned);
+        assert_eq!(titles(&live), vec!["a", "b", "g"]);
+        assert_eq!(live.tracks[0].artist.as_deref(), Some("Watched"));
+    }
+}
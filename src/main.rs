@@ -1,11 +1,31 @@
 mod app;
+mod cli;
+mod config;
+mod daemon;
 mod search;
+mod sources;
 mod stream;
+mod cache;
 mod download;
+mod history;
+mod library;
+mod playlists;
+mod plugins;
+mod podcasts;
+mod positions;
+mod radio;
+mod recorder;
+mod replaygain;
+mod naming;
+mod network;
+mod tags;
 mod ui;
+mod xml;
 
 use std::error::Error;
 use std::io;
+use std::path::Path;
+use std::process::{ Command, Stdio };
 use std::time::{ Duration, Instant };
 use std::sync::Arc;
 use crossterm::event::KeyEvent;
@@ -17,13 +37,25 @@ use crossterm::{
 use ratatui::prelude::*;
 use tokio::main;
 
-use app::{ AppUi, Mode, Source, View };
-use stream::stream_audio;
-use download::{ download_youtube_audio, download_archive_audio };
+use app::{ AppUi, BrowseEntries, Mode, PromptKind, View };
+use config::expand_home;
+use naming::sanitize_component;
+use playlists::{ export_playlist, import_playlist };
+use library::Facet;
+use sources::{ MusicSource, PODCAST };
+use stream::step_speed;
 use ui::render;
 
 #[main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    // With a subcommand it runs without the TUI, see cli.rs
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        // Sources block on yt-dlp and HTTP requests, keep that off the runtime
+        let code = tokio::task::spawn_blocking(move || cli::run(args)).await?;
+        std::process::exit(code);
+    }
+
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen)?;
@@ -31,11 +63,27 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let mut terminal = Terminal::new(backend)?;
 
     let mut app = AppUi::new();
+    app.attach_daemon();
+    // An attached window leaves the library and analysis to the daemon
+    if app.daemon.is_none() {
+        app.start_workers();
+    }
+    // The terminal is put back however the loop ends
+    let result = run(&mut terminal, &mut app).await;
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+
+    result
+}
+
+async fn run(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, app: &mut AppUi) -> Result<(), Box<dyn Error>> {
     let tick_rate = Duration::from_millis(250);
     let mut last_tick = Instant::now();
 
     loop {
-        terminal.draw(|frame| render(&app, frame))?;
+        terminal.draw(|frame| render(app, frame))?;
 
         let timeout = tick_rate
             .checked_sub(last_tick.elapsed())
@@ -50,29 +98,42 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 {
                     break;
                 }
-                handle_key_event(&mut app, key).await?;
+                // A failed action (ffplay missing, say) is shown, not fatal
+                if let Err(e) = handle_key_event(app, key).await {
+                    app.notice = Some(e.to_string());
+                }
             }
         }
 
         if last_tick.elapsed() >= tick_rate {
+            if let Err(e) = app.on_tick() {
+                app.notice = Some(e.to_string());
+            }
             last_tick = Instant::now();
         }
     }
 
-    disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
-    terminal.show_cursor()?;
-
     Ok(())
 }
 
 async fn handle_key_event(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+    app.notice = None;
     match app.current_view {
         View::SearchInput => handle_search_input(app, key).await,
         View::InitialSelection => handle_initial_selection(app, key).await,
         View::SourceSelection => handle_source_selection(app, key).await,
         View::SearchResults => handle_search_results(app, key).await,
         View::Streaming => handle_streaming(app, key).await,
+        View::DownloadOptions => handle_download_options(app, key).await,
+        View::DownloadExists => handle_download_exists(app, key).await,
+        View::Library => handle_library(app, key).await,
+        View::Playlists => handle_playlists(app, key).await,
+        View::PlaylistTracks => handle_playlist_tracks(app, key).await,
+        View::Podcasts => handle_podcasts(app, key).await,
+        View::PodcastEpisodes => handle_podcast_episodes(app, key).await,
+        View::Cache => handle_cache(app, key).await,
+        View::Resume => handle_resume(app, key).await,
+        View::Prompt => handle_prompt(app, key).await,
         View::Downloading => handle_downloading(app, key).await,
     }
 }
@@ -101,23 +162,56 @@ async fn handle_initial_selection(app: &mut AppUi, key: KeyEvent) -> Result<(),
             );
         }
         KeyCode::Down => {
-            app.selected_result_index = Some((app.selected_result_index.unwrap_or(0) + 1).min(1));
+            app.selected_result_index = Some((app.selected_result_index.unwrap_or(0) + 1).min(5));
         }
         KeyCode::Enter | KeyCode::Right => {
             match app.selected_result_index {
                 Some(0) => {
                     app.mode = Some(Mode::Stream);
-                    app.source = Source::YouTube;
-                    app.search().await?;
-                    app.current_view = View::SearchResults;
+                    app.selected_source_index = 0;
+                    if !open_link(app).await? {
+                        app.current_view = View::SourceSelection;
+                    }
+                }
+                Some(1) if app.is_offline() => {
+                    app.notice = Some("Downloading needs a network connection".to_string());
                 }
                 Some(1) => {
                     app.mode = Some(Mode::Download);
-                    app.current_view = View::SourceSelection;
+                    app.selected_source_index = 0;
+                    if !open_link(app).await? {
+                        app.current_view = View::SourceSelection;
+                    }
+                }
+                Some(2) => {
+                    app.mode = Some(Mode::Stream);
+                    app.reload_library();
+                    app.browse_filters.clear();
+                    app.browse_facet = None;
+                    app.browse_index = 0;
+                    app.current_view = View::Library;
+                }
+                Some(3) => {
+                    app.mode = Some(Mode::Stream);
+                    app.playlist_index = 0;
+                    app.current_view = View::Playlists;
+                }
+                Some(4) => {
+                    app.mode = Some(Mode::Stream);
+                    app.podcast_index = 0;
+                    app.current_view = View::Podcasts;
+                }
+                Some(5) => {
+                    app.cache_index = 0;
+                    app.refresh_cache_entries();
+                    app.current_view = View::Cache;
                 }
                 _ => {}
             }
         }
+        KeyCode::Char('o' | 'O') => {
+            app.toggle_offline();
+        }
         KeyCode::Left => {
             app.current_view = View::SearchInput;
         }
@@ -126,22 +220,73 @@ async fn handle_initial_selection(app: &mut AppUi, key: KeyEvent) -> Result<(),
     Ok(())
 }
 
+// A link in the search box skips source selection: playlists and channels
+// are listed, anything else is played or downloaded straight away. Returns
+// false when the input is an ordinary search.
+async fn open_link(app: &mut AppUi) -> Result<bool, Box<dyn Error>> {
+    if let Some(source) = app.sources.collection_source(&app.search_input) {
+        app.source = source;
+        search(app).await;
+        return Ok(true);
+    }
+    let Some(result) = app.sources.track_from_url(&app.search_input) else {
+        return Ok(false);
+    };
+    if let Some(source) = app.sources.get(&result.source) {
+        app.source = source;
+    }
+    app.search_results = vec![result.clone()];
+    app.playlist_title = None;
+    app.marked_results.clear();
+    app.selected_result_index = Some(0);
+    app.current_view = View::SearchResults;
+    match app.mode {
+        Some(Mode::Stream) => {
+            app.back_view = View::SearchResults;
+            app.queue.clear();
+            app.play(result)?;
+        }
+        Some(Mode::Download) => {
+            if app.is_downloaded(&result) {
+                app.selected_option_index = 0;
+                app.current_view = View::DownloadExists;
+            } else {
+                start_download(app);
+            }
+        }
+        None => {}
+    }
+    Ok(true)
+}
+
+// Runs the search for the chosen source. A failing source leaves a notice
+// instead of ending the session.
+async fn search(app: &mut AppUi) {
+    if app.is_offline() && !app.source.works_offline() {
+        app.notice = Some(format!("{} needs a network connection", app.source.label()));
+        return;
+    }
+    if let Err(e) = app.search().await {
+        app.notice = Some(format!("{} search failed: {}", app.source.label(), e));
+    }
+}
+
 async fn handle_source_selection(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
     match key.code {
         KeyCode::Up => {
             app.selected_source_index = app.selected_source_index.saturating_sub(1);
         }
         KeyCode::Down => {
-            app.selected_source_index = (app.selected_source_index + 1).min(1);
+            let last = app.available_sources().len().saturating_sub(1);
+            app.selected_source_index = (app.selected_source_index + 1).min(last);
         }
         KeyCode::Enter | KeyCode::Right => {
-            app.source = match app.selected_source_index {
-                0 => Source::YouTube,
-                1 => Source::InternetArchive,
-                _ => Source::YouTube,
-            };
-            app.search().await?;
-            app.current_view = View::SearchResults;
+            app.source = app
+                .available_sources()
+                .get(app.selected_source_index)
+                .cloned()
+                .unwrap_or_else(|| Arc::clone(&app.sources.all()[0]));
+            search(app).await;
         }
         KeyCode::Left => {
             app.current_view = View::InitialSelection;
@@ -173,67 +318,116 @@ async fn handle_search_results(app: &mut AppUi, key: KeyEvent) -> Result<(), Box
                 let selected = &app.search_results[index];
                 match app.mode {
                     Some(Mode::Stream) => {
-                        app.current_view = View::Streaming;
-                        let identifier = selected.identifier.clone();
-                        let visualization_data = Arc::clone(&app.visualization_data);
-                        let ffplay_process = stream_audio(
-                            &identifier,
-                            visualization_data /* third_argument */
-                        )?;
-                        app.ffplay_process = Some(ffplay_process);
-                        app.paused = false;
+                        let selected = selected.clone();
+                        app.back_view = View::SearchResults;
+                        app.queue.clear();
+                        app.play(selected)?;
                     }
                     Some(Mode::Download) => {
-                        app.current_view = View::Downloading;
-                        match app.source {
-                            Source::YouTube => {
-                                download_youtube_audio(
-                                    selected.identifier.clone(),
-                                    selected.title.clone(),
-                                    Arc::clone(&app.download_status)
-                                );
-                            }
-                            Source::InternetArchive => {
-                                download_archive_audio(
-                                    selected.identifier.clone(),
-                                    selected.title.clone(),
-                                    Arc::clone(&app.download_status)
-                                );
-                            }
+                        if !app.marked_results.is_empty() {
+                            // Marked playlist entries go out as one batch
+                            start_download(app);
+                        } else if app.is_downloaded(selected) {
+                            app.selected_option_index = 0;
+                            app.current_view = View::DownloadExists;
+                        } else {
+                            start_download(app);
                         }
                     }
                     _ => {}
                 }
             }
         }
-        KeyCode::Left => {
-            match app.mode {
-                Some(Mode::Stream) => {
-                    app.current_view = View::InitialSelection;
-                }
-                Some(Mode::Download) => {
-                    app.current_view = View::SourceSelection;
-                }
-                _ => {}
+        KeyCode::Char(' ') if app.playlist_title.is_some() => {
+            if let Some(index) = app.selected_result_index {
+                app.toggle_mark(index);
+            }
+        }
+        KeyCode::Char('a') if app.playlist_title.is_some() => {
+            app.toggle_mark_all();
+        }
+        KeyCode::Char('+') => {
+            if let Some(result) = app.selected_result_index.and_then(|i| app.search_results.get(i)) {
+                app.add_to_playlist(vec![result.clone()]);
             }
         }
+        KeyCode::Char('f') => {
+            if let Some(result) = app.selected_result_index.and_then(|i| app.search_results.get(i)) {
+                app.toggle_radio_favorite(&result.clone());
+            }
+        }
+        KeyCode::Left => {
+            app.current_view = View::SourceSelection;
+        }
         _ => {}
     }
     Ok(())
 }
 
+// Playback keys, as commands for the daemon when attached to one
+fn daemon_command(key: KeyCode) -> Option<&'static str> {
+    match key {
+        KeyCode::Char(' ') => Some("pause"),
+        KeyCode::Char('n') => Some("next"),
+        KeyCode::Char('r') => Some("keep"),
+        KeyCode::Char(']') => Some("faster"),
+        KeyCode::Char('[') => Some("slower"),
+        KeyCode::Char('s') => Some("smart_speed"),
+        KeyCode::Char('l') => Some("normalize"),
+        KeyCode::Char('g') => Some("replaygain"),
+        _ => None,
+    }
+}
+
 async fn handle_streaming(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+    if let (Some(_), Some(command)) = (&app.daemon, daemon_command(key.code)) {
+        app.send_to_daemon(command);
+        return Ok(());
+    }
     match key.code {
         KeyCode::Esc | KeyCode::Left => {
             app.stop_streaming();
-            app.current_view = View::SearchResults;
+            app.queue.clear();
+            app.current_view = app.back_view;
         }
         KeyCode::Char(' ') => {
             app.toggle_pause()?;
         }
-        KeyCode::Char(c) if c.is_digit(10) => {
+        KeyCode::Char('n') => {
+            app.play_next()?;
+        }
+        KeyCode::Char('+') => {
+            if let Some(result) = app.now_playing.clone() {
+                app.add_to_playlist(vec![result]);
+            }
+        }
+        KeyCode::Char('f') => {
+            if let Some(result) = app.now_playing.clone() {
+                app.toggle_radio_favorite(&result);
+            }
+        }
+        // Record the station, or keep the track
+        KeyCode::Char('r') => {
+            app.keep_playing();
+        }
+        KeyCode::Char(']') => {
+            app.change_speed(true)?;
+        }
+        KeyCode::Char('[') => {
+            app.change_speed(false)?;
+        }
+        KeyCode::Char('s') => {
+            app.toggle_smart_speed()?;
+        }
+        KeyCode::Char('l') => {
+            app.toggle_normalize()?;
+        }
+        KeyCode::Char('g') => {
+            app.cycle_gain_mode()?;
+        }
+        KeyCode::Char(c) if c.is_ascii_digit() => {
             let digit = c.to_digit(10).unwrap() as usize;
-            if digit >= 1 && digit <= 6 {
+            if (1..=6).contains(&digit) {
                 app.current_equalizer = digit - 1; // Map 1-6 to 0-5 for equalizer index
             }
         }
@@ -242,9 +436,569 @@ async fn handle_streaming(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn
     Ok(())
 }
 
+async fn handle_resume(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+    match key.code {
+        KeyCode::Up => {
+            app.selected_option_index = 0;
+        }
+        KeyCode::Down => {
+            app.selected_option_index = 1;
+        }
+        KeyCode::Enter | KeyCode::Right => {
+            if let Some((track, position)) = app.pending_resume.take() {
+                if app.selected_option_index == 0 {
+                    app.start_playback(track, position)?;
+                } else {
+                    app.reload_positions();
+                    app.positions.forget(&track);
+                    app.positions.save()?;
+                    app.start_playback(track, 0)?;
+                }
+            }
+        }
+        KeyCode::Left => {
+            app.pending_resume = None;
+            app.queue.clear();
+            app.current_view = app.back_view;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn handle_library(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+    let entries = app.browse_entries();
+    let count = match &entries {
+        BrowseEntries::Facets => Facet::ALL.len(),
+        BrowseEntries::Values(_, values) => values.len(),
+        BrowseEntries::Tracks(tracks) => tracks.len(),
+    };
+    match key.code {
+        KeyCode::Up => {
+            app.browse_index = app.browse_index.saturating_sub(1);
+        }
+        KeyCode::Down => {
+            app.browse_index = (app.browse_index + 1).min(count.saturating_sub(1));
+        }
+        KeyCode::Char('s') => {
+            app.browse_sort = app.browse_sort.next();
+        }
+        KeyCode::Left if matches!(entries, BrowseEntries::Facets) => {
+            app.current_view = View::InitialSelection;
+        }
+        KeyCode::Left => {
+            app.browse_back();
+        }
+        KeyCode::Enter | KeyCode::Right => {
+            match entries {
+                BrowseEntries::Facets => {
+                    app.browse_facet = Facet::ALL.get(app.browse_index).copied();
+                    app.browse_index = 0;
+                }
+                BrowseEntries::Values(facet, values) => {
+                    if let Some((value, _)) = values.into_iter().nth(app.browse_index) {
+                        app.browse_into(facet, value);
+                    }
+                }
+                BrowseEntries::Tracks(tracks) => {
+                    // Start at the selected track and keep the rest of the list queued
+                    let tracks = tracks.into_iter().skip(app.browse_index).collect();
+                    app.back_view = View::Library;
+                    app.play_all(tracks)?;
+                }
+            }
+        }
+        // Play the selected album (or everything under the selected value)
+        KeyCode::Char('p') => {
+            let tracks = match entries {
+                BrowseEntries::Values(facet, values) =>
+                    match values.get(app.browse_index) {
+                        Some((value, _)) => app.browse_tracks_under(facet, value),
+                        None => Vec::new(),
+                    }
+                BrowseEntries::Tracks(tracks) => tracks,
+                BrowseEntries::Facets => Vec::new(),
+            };
+            app.back_view = View::Library;
+            app.play_all(tracks)?;
+        }
+        KeyCode::Char('+') => {
+            let tracks = match entries {
+                BrowseEntries::Values(facet, values) =>
+                    match values.get(app.browse_index) {
+                        Some((value, _)) => app.browse_tracks_under(facet, value),
+                        None => Vec::new(),
+                    }
+                BrowseEntries::Tracks(tracks) =>
+                    tracks.into_iter().nth(app.browse_index).into_iter().collect(),
+                BrowseEntries::Facets => Vec::new(),
+            };
+            app.add_to_playlist(tracks);
+        }
+        KeyCode::Char('a') => {
+            let tracks = match entries {
+                BrowseEntries::Values(facet, values) =>
+                    match values.get(app.browse_index) {
+                        Some((value, _)) => app.browse_tracks_under(facet, value),
+                        None => Vec::new(),
+                    }
+                BrowseEntries::Tracks(tracks) =>
+                    tracks.into_iter().nth(app.browse_index).into_iter().collect(),
+                BrowseEntries::Facets => Vec::new(),
+            };
+            app.queue.extend(tracks);
+            // Nothing playing yet, so the queue starts right away
+            if !app.is_playing() {
+                app.back_view = View::Library;
+                app.play_next()?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn handle_playlists(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+    app.reload_playlists();
+    let count = app.playlists.playlists.len();
+    match key.code {
+        KeyCode::Up => {
+            app.playlist_index = app.playlist_index.saturating_sub(1);
+        }
+        KeyCode::Down => {
+            app.playlist_index = (app.playlist_index + 1).min(count.saturating_sub(1));
+        }
+        KeyCode::Enter | KeyCode::Right if app.playlist_index < count => {
+            app.active_playlist = Some(app.playlist_index);
+            app.playlist_track_index = 0;
+            app.current_view = View::PlaylistTracks;
+        }
+        KeyCode::Char('n') => {
+            app.open_prompt(PromptKind::PlaylistName, String::new());
+        }
+        KeyCode::Char('i') => {
+            app.open_prompt(PromptKind::ImportPath, String::new());
+        }
+        KeyCode::Char('e') if app.playlist_index < count => {
+            let name = &app.playlists.playlists[app.playlist_index].name;
+            let path = app.config.download_dir.join(
+                format!("{}.m3u8", sanitize_component(name, false))
+            );
+            app.open_prompt(PromptKind::ExportPath, path.to_string_lossy().to_string());
+        }
+        KeyCode::Char('d') if app.playlist_index < count => {
+            app.playlists.playlists.remove(app.playlist_index);
+            app.playlists.save()?;
+            app.active_playlist = None;
+            app.playlist_index = app.playlist_index.min(count.saturating_sub(2));
+        }
+        KeyCode::Char('p') if app.playlist_index < count => {
+            let tracks = app.playlists.playlists[app.playlist_index].tracks.clone();
+            app.back_view = View::Playlists;
+            app.play_all(tracks)?;
+        }
+        KeyCode::Left => {
+            app.current_view = View::InitialSelection;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn handle_playlist_tracks(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+    app.reload_playlists();
+    let Some(playlist) = app.playlists.playlists.get_mut(app.playlist_index) else {
+        app.current_view = View::Playlists;
+        return Ok(());
+    };
+    let count = playlist.tracks.len();
+    let index = app.playlist_track_index;
+    match key.code {
+        KeyCode::Up => {
+            app.playlist_track_index = index.saturating_sub(1);
+        }
+        KeyCode::Down => {
+            app.playlist_track_index = (index + 1).min(count.saturating_sub(1));
+        }
+        KeyCode::Enter | KeyCode::Right if index < count => {
+            // Start at the selected track and keep the rest of the playlist queued
+            let tracks = playlist.tracks[index..].to_vec();
+            app.back_view = View::PlaylistTracks;
+            app.play_all(tracks)?;
+        }
+        KeyCode::Char('p') => {
+            let tracks = playlist.tracks.clone();
+            app.back_view = View::PlaylistTracks;
+            app.play_all(tracks)?;
+        }
+        KeyCode::Char('d') if index < count => {
+            playlist.tracks.remove(index);
+            app.playlist_track_index = index.min(count.saturating_sub(2));
+            app.playlists.save()?;
+        }
+        // Move the selected track up or down
+        KeyCode::Char('K') if index > 0 && index < count => {
+            playlist.tracks.swap(index, index - 1);
+            app.playlist_track_index = index - 1;
+            app.playlists.save()?;
+        }
+        KeyCode::Char('J') if index + 1 < count => {
+            playlist.tracks.swap(index, index + 1);
+            app.playlist_track_index = index + 1;
+            app.playlists.save()?;
+        }
+        KeyCode::Left => {
+            app.current_view = View::Playlists;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn handle_podcasts(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+    app.reload_podcasts();
+    let count = app.podcasts.lock().unwrap().podcasts.len();
+    let unsubscribe_pending = app.unsubscribe_pending.take();
+    match key.code {
+        KeyCode::Up => {
+            app.podcast_index = app.podcast_index.saturating_sub(1);
+        }
+        KeyCode::Down => {
+            app.podcast_index = (app.podcast_index + 1).min(count.saturating_sub(1));
+        }
+        KeyCode::Enter | KeyCode::Right if app.podcast_index < count => {
+            app.episode_index = 0;
+            app.current_view = View::PodcastEpisodes;
+        }
+        KeyCode::Char('n') => {
+            app.open_prompt(PromptKind::FeedUrl, String::new());
+        }
+        // Refresh the selected feed, or all of them
+        KeyCode::Char('r') if app.podcast_index < count => {
+            let url = app.podcasts.lock().unwrap().podcasts[app.podcast_index].feed_url.clone();
+            app.notice = Some(app.refresh_podcasts(vec![url]).await?);
+        }
+        KeyCode::Char('R') => {
+            let urls = app.podcasts
+                .lock()
+                .unwrap()
+                .podcasts.iter()
+                .map(|podcast| podcast.feed_url.clone())
+                .collect();
+            app.notice = Some(app.refresh_podcasts(urls).await?);
+        }
+        // Not D, which downloads in the episode list. Asks first, the
+        // feed's played marks go with it.
+        KeyCode::Char('u') if app.podcast_index < count => {
+            let mut podcasts = app.podcasts.lock().unwrap();
+            let podcast = &podcasts.podcasts[app.podcast_index];
+            if unsubscribe_pending.as_deref() != Some(podcast.feed_url.as_str()) {
+                app.notice = Some(format!("Press U again to unsubscribe from {} and forget what you played", podcast.title));
+                app.unsubscribe_pending = Some(podcast.feed_url.clone());
+                return Ok(());
+            }
+            let podcast = podcasts.podcasts.remove(app.podcast_index);
+            podcasts.save()?;
+            app.podcast_index = app.podcast_index.min(count.saturating_sub(2));
+            app.notice = Some(format!("Unsubscribed from {}", podcast.title));
+        }
+        // Default speed for the selected feed's episodes
+        KeyCode::Char(c @ ('[' | ']')) if app.podcast_index < count => {
+            let source_speed = app.config.playback_speed.get(PODCAST).copied().unwrap_or(1.0);
+            let mut podcasts = app.podcasts.lock().unwrap();
+            let podcast = &mut podcasts.podcasts[app.podcast_index];
+            let speed = step_speed(podcast.speed.unwrap_or(source_speed), c == ']');
+            podcast.speed = Some(speed);
+            let title = podcast.title.clone();
+            podcasts.save()?;
+            app.notice = Some(format!("{} plays at {}x", title, speed));
+        }
+        KeyCode::Left => {
+            app.current_view = View::InitialSelection;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn handle_cache(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+    let count = app.cache_entries.len();
+    match key.code {
+        KeyCode::Up => {
+            app.cache_index = app.cache_index.saturating_sub(1);
+        }
+        KeyCode::Down => {
+            app.cache_index = (app.cache_index + 1).min(count.saturating_sub(1));
+        }
+        KeyCode::Char('d') | KeyCode::Delete if app.cache_index < count => {
+            let entry = app.cache_entries[app.cache_index].clone();
+            app.cache.remove(&entry);
+            app.refresh_cache_entries();
+            app.notice = Some(format!("Removed {} from the cache", entry.title));
+        }
+        KeyCode::Char('c') => {
+            app.cache.clear();
+            app.refresh_cache_entries();
+            app.notice = Some("Audio cache cleared".to_string());
+        }
+        KeyCode::Left => {
+            app.current_view = View::InitialSelection;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn handle_podcast_episodes(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+    let (count, selected) = {
+        let podcasts = app.podcasts.lock().unwrap();
+        match podcasts.podcasts.get(app.podcast_index) {
+            Some(podcast) =>
+                (
+                    podcast.episodes.len(),
+                    podcast.episodes
+                        .get(app.episode_index)
+                        .map(|episode| (podcast.episode_result(episode), episode.played)),
+                ),
+            None => {
+                app.current_view = View::Podcasts;
+                return Ok(());
+            }
+        }
+    };
+    match key.code {
+        KeyCode::Up => {
+            app.episode_index = app.episode_index.saturating_sub(1);
+        }
+        KeyCode::Down => {
+            app.episode_index = (app.episode_index + 1).min(count.saturating_sub(1));
+        }
+        KeyCode::Left => {
+            app.current_view = View::Podcasts;
+        }
+        _ => {}
+    }
+    let Some((episode, played)) = selected else {
+        return Ok(());
+    };
+    match key.code {
+        KeyCode::Enter | KeyCode::Right => {
+            app.back_view = View::PodcastEpisodes;
+            app.queue.clear();
+            app.play(episode)?;
+        }
+        // Episodes download through the same path as search results
+        KeyCode::Char('d') => {
+            if let Some(source) = app.sources.get(&episode.source) {
+                app.download(source, episode);
+                app.download_back_view = View::PodcastEpisodes;
+                app.current_view = View::Downloading;
+            }
+        }
+        KeyCode::Char('m') => {
+            app.set_episode_played(&episode, !played);
+        }
+        KeyCode::Char('+') => {
+            app.add_to_playlist(vec![episode]);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn handle_prompt(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+    let Some(prompt) = &mut app.prompt else {
+        app.current_view = View::InitialSelection;
+        return Ok(());
+    };
+    match key.code {
+        KeyCode::Char(c) => {
+            prompt.input.push(c);
+        }
+        KeyCode::Backspace => {
+            prompt.input.pop();
+        }
+        KeyCode::Left => {
+            app.current_view = prompt.return_view;
+            app.prompt = None;
+        }
+        KeyCode::Enter => {
+            let prompt = app.prompt.take().unwrap();
+            app.current_view = prompt.return_view;
+            submit_prompt(app, prompt.kind, prompt.input.trim()).await;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn submit_prompt(app: &mut AppUi, kind: PromptKind, input: &str) {
+    let result: Result<String, Box<dyn Error>> = match kind {
+        PromptKind::PlaylistName => {
+            app.reload_playlists();
+            let index = app.playlists.create(input, Vec::new());
+            app.playlist_index = index;
+            app.active_playlist = Some(index);
+            app.playlists
+                .save()
+                .map(|_| format!("Created {}", app.playlists.playlists[index].name))
+                .map_err(|e| e.into())
+        }
+        PromptKind::ImportPath => {
+            import_playlist(&expand_home(input), &app.sources).and_then(|playlist| {
+                app.reload_playlists();
+                let count = playlist.tracks.len();
+                let index = app.playlists.create(&playlist.name, playlist.tracks);
+                app.playlist_index = index;
+                app.playlists.save()?;
+                Ok(format!("Imported {} with {} tracks", app.playlists.playlists[index].name, count))
+            })
+        }
+        PromptKind::ExportPath => {
+            let path = expand_home(input);
+            match app.playlists.playlists.get(app.playlist_index) {
+                Some(playlist) =>
+                    export_playlist(playlist, &path, &app.sources).map(|_| format!("Exported to {}", path.display())),
+                None => Err("No playlist selected".into()),
+            }
+        }
+        PromptKind::FeedUrl => {
+            let summary = app.refresh_podcasts(vec![input.to_string()]).await;
+            if let Some(index) = app.podcasts.lock().unwrap().position(input) {
+                app.podcast_index = index;
+            }
+            summary
+        }
+    };
+    app.notice = Some(match result {
+        Ok(message) => message,
+        Err(e) => format!("Error: {}", e),
+    });
+}
+
+// yt-dlp downloads go through the options dialog first, Archive ones start right away
+fn start_download(app: &mut AppUi) {
+    let Some(index) = app.selected_result_index else {
+        return;
+    };
+    let selected = &app.search_results[index];
+    let Some(source) = app.sources.get(&selected.source) else {
+        return;
+    };
+    if !source.can_download() {
+        return;
+    }
+    if source.has_download_options(selected) {
+        app.selected_option_index = 0;
+        app.current_view = View::DownloadOptions;
+    } else {
+        run_download(app, source);
+    }
+}
+
+// Downloads the marked playlist entries as one batch, or else the selected result
+fn run_download(app: &mut AppUi, source: Arc<dyn MusicSource>) {
+    if let (Some(title), false) = (&app.playlist_title, app.marked_results.is_empty()) {
+        let mut marked: Vec<usize> = app.marked_results.drain().collect();
+        marked.sort_unstable();
+        let entries = marked
+            .into_iter()
+            .map(|i| ((i + 1) as u32, app.search_results[i].clone()))
+            .collect();
+        let title = title.clone();
+        app.download_playlist(source, title, entries);
+        app.download_back_view = View::SearchResults;
+        app.current_view = View::Downloading;
+    } else if let Some(index) = app.selected_result_index {
+        let track = app.search_results[index].clone();
+        app.download(source, track);
+        app.download_back_view = View::SearchResults;
+        app.current_view = View::Downloading;
+    }
+}
+
+async fn handle_download_exists(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+    match key.code {
+        KeyCode::Up => {
+            app.selected_option_index = app.selected_option_index.saturating_sub(1);
+        }
+        KeyCode::Down => {
+            app.selected_option_index = (app.selected_option_index + 1).min(2);
+        }
+        KeyCode::Enter | KeyCode::Right => {
+            match app.selected_option_index {
+                0 => {
+                    if let Some(index) = app.selected_result_index {
+                        let selected = &app.search_results[index];
+                        let existing = app.history
+                            .lock()
+                            .unwrap()
+                            .find(&selected.source, &selected.identifier)
+                            .map(|entry| entry.path.clone());
+                        if let Some(path) = existing {
+                            open_file(&path)?;
+                        }
+                    }
+                    app.current_view = View::SearchResults;
+                }
+                1 => start_download(app),
+                _ => {
+                    app.current_view = View::SearchResults;
+                }
+            }
+        }
+        KeyCode::Left => {
+            app.current_view = View::SearchResults;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// Hands the file to the desktop's default application
+fn open_file(path: &Path) -> Result<(), Box<dyn Error>> {
+    let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+    Command::new(opener).arg(path).stdout(Stdio::null()).stderr(Stdio::null()).spawn()?;
+    Ok(())
+}
+
+async fn handle_download_options(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
+    match key.code {
+        KeyCode::Up => {
+            app.selected_option_index = app.selected_option_index.saturating_sub(1);
+        }
+        KeyCode::Down => {
+            app.selected_option_index = (app.selected_option_index + 1).min(2);
+        }
+        KeyCode::Enter | KeyCode::Right => {
+            match app.selected_option_index {
+                0 => {
+                    app.download_options.format = app.download_options.format.next();
+                }
+                1 => {
+                    app.download_options.quality = app.download_options.quality.next();
+                }
+                _ => {
+                    let source = app.selected_result_index.and_then(|index|
+                        app.sources.get(&app.search_results[index].source)
+                    );
+                    if let Some(source) = source {
+                        run_download(app, source);
+                    }
+                }
+            }
+        }
+        KeyCode::Left => {
+            app.current_view = View::SearchResults;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
 async fn handle_downloading(app: &mut AppUi, key: KeyEvent) -> Result<(), Box<dyn Error>> {
     if key.code == KeyCode::Left || key.code == KeyCode::Esc {
-        app.current_view = View::SearchResults;
+        app.current_view = app.download_back_view;
         let mut download_status = app.download_status.lock().unwrap();
         *download_status = None;
     }
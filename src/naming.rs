@@ -0,0 +1,161 @@
+// naming.rs
+use std::path::{ Path, PathBuf };
+use crate::tags::TrackMetadata;
+
+pub const DEFAULT_TEMPLATE: &str = "{title} (PJ-PLAYER).{ext}";
+
+// Most filesystems cap a single path component at 255 bytes
+const MAX_COMPONENT_BYTES: usize = 255;
+
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CollisionPolicy {
+    Skip,
+    Overwrite,
+    NumberSuffix,
+}
+
+impl CollisionPolicy {
+    pub fn from_name(name: &str) -> Option<CollisionPolicy> {
+        match name {
+            "skip" => Some(CollisionPolicy::Skip),
+            "overwrite" => Some(CollisionPolicy::Overwrite),
+            "number" | "number_suffix" => Some(CollisionPolicy::NumberSuffix),
+            _ => None,
+        }
+    }
+}
+
+/// Expands a template such as `{artist}/{album}/{track:02} - {title}.{ext}`
+/// into a path relative to the download folder. Every `/` in the template
+/// starts a new folder, and each folder or file name is sanitized on its own.
+pub fn render_template(template: &str, metadata: &TrackMetadata, extension: &str) -> PathBuf {
+    let template = if template.trim().is_empty() { DEFAULT_TEMPLATE } else { template };
+    let components: Vec<&str> = template.split('/').filter(|c| !c.is_empty()).collect();
+    let mut path = PathBuf::new();
+    for (i, component) in components.iter().enumerate() {
+        let rendered = expand_placeholders(component, metadata, extension);
+        let is_file_name = i == components.len() - 1;
+        path.push(sanitize_component(&rendered, is_file_name));
+    }
+    path
+}
+
+fn expand_placeholders(component: &str, metadata: &TrackMetadata, extension: &str) -> String {
+    let mut output = String::new();
+    let mut rest = component;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            output.push_str(&rest[start..]);
+            return output;
+        };
+        let placeholder = &rest[start + 1..start + end];
+        output.push_str(&placeholder_value(placeholder, metadata, extension));
+        rest = &rest[start + end + 1..];
+    }
+    output.push_str(rest);
+    output
+}
+
+fn placeholder_value(placeholder: &str, metadata: &TrackMetadata, extension: &str) -> String {
+    let (name, width) = match placeholder.split_once(':') {
+        Some((name, width)) => (name, width.parse::<usize>().ok()),
+        None => (placeholder, None),
+    };
+    match name {
+        "title" => metadata.title.clone(),
+        "artist" => metadata.artist.clone().unwrap_or_else(|| "Unknown Artist".to_string()),
+        "album" => metadata.album.clone().unwrap_or_else(|| "Unknown Album".to_string()),
+        "year" => metadata.year.clone().unwrap_or_default(),
+        "track" =>
+            match metadata.track {
+                Some(track) => format!("{:0width$}", track, width = width.unwrap_or(0)),
+                None => String::new(),
+            }
+        "ext" => extension.to_string(),
+        // Unknown placeholders are kept verbatim so typos are easy to spot
+        _ => format!("{{{}}}", placeholder),
+    }
+}
+
+/// Makes a single path component safe on Linux, macOS and Windows filesystems:
+/// strips control and reserved characters, avoids reserved device names and
+/// trims it to the byte limit while keeping the file extension.
+pub fn sanitize_component(component: &str, is_file_name: bool) -> String {
+    let cleaned: String = component
+        .chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    // Leftovers of empty placeholders ("{track:02} - " without a track) and
+    // trailing dots or spaces, which Windows refuses
+    let cleaned = cleaned
+        .trim_start_matches(|c: char| c.is_whitespace() || c == '-' || c == '.')
+        .trim_end_matches(|c: char| c.is_whitespace() || c == '.')
+        .to_string();
+
+    let (stem, extension) = match cleaned.rfind('.') {
+        Some(dot) if is_file_name && dot > 0 => (&cleaned[..dot], &cleaned[dot..]),
+        _ => (cleaned.as_str(), ""),
+    };
+    let mut stem = stem.trim_end().to_string();
+    if stem.is_empty() {
+        stem.push('_');
+    }
+
+    let base = stem.split('.').next().unwrap_or("").to_uppercase();
+    if RESERVED_NAMES.contains(&base.as_str()) {
+        stem.insert(0, '_');
+    }
+
+    let budget = MAX_COMPONENT_BYTES.saturating_sub(extension.len());
+    if stem.len() > budget {
+        let mut cut = budget;
+        while !stem.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        stem.truncate(cut);
+    }
+
+    format!("{}{}", stem, extension)
+}
+
+/// Applies the collision policy to `path`. Returns `None` when the download
+/// should be skipped because the file already exists.
+pub fn resolve_collision(path: &Path, policy: CollisionPolicy) -> Option<PathBuf> {
+    if !path.exists() {
+        return Some(path.to_path_buf());
+    }
+    match policy {
+        CollisionPolicy::Skip => None,
+        CollisionPolicy::Overwrite => Some(path.to_path_buf()),
+        CollisionPolicy::NumberSuffix => {
+            let stem = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("_");
+            let extension = path.extension().and_then(|e| e.to_str());
+            (1..)
+                .map(|n| {
+                    let name = match extension {
+                        Some(ext) => format!("{} ({}).{}", stem, n, ext),
+                        None => format!("{} ({})", stem, n),
+                    };
+                    path.with_file_name(name)
+                })
+                .find(|candidate| !candidate.exists())
+        }
+    }
+}
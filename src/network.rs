@@ -0,0 +1,32 @@
+// network.rs
+// Whether the network is there, checked in the background so searches and
+// playback can tell the user instead of failing one by one.
+use std::net::{ TcpStream, ToSocketAddrs };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Hosts most sources live on, any one of them answering is enough
+const CHECK_HOSTS: [&str; 2] = ["www.youtube.com:443", "archive.org:443"];
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+fn is_reachable(host: &str) -> bool {
+    host.to_socket_addrs()
+        .map(|mut addresses| {
+            addresses.any(|address| TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).is_ok())
+        })
+        .unwrap_or(false)
+}
+
+/// Keeps `online` up to date for as long as the app runs
+pub fn spawn_connectivity_check(online: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        loop {
+            let reachable = CHECK_HOSTS.iter().any(|host| is_reachable(host));
+            online.store(reachable, Ordering::Relaxed);
+            thread::sleep(CHECK_INTERVAL);
+        }
+    });
+}
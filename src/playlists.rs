@@ -0,0 +1,332 @@
+// playlists.rs
+use std::error::Error;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use reqwest::Url;
+use serde_json::{ json, Value };
+use crate::app::SearchResult;
+use crate::config::data_dir;
+use crate::sources::{ SourceRegistry, LOCAL };
+use crate::xml;
+
+#[derive(Debug, Clone)]
+pub struct Playlist {
+    pub name: String,
+    pub tracks: Vec<SearchResult>,
+}
+
+/// Named playlists, persisted as `playlists.json` in the data directory
+#[derive(Debug, Default)]
+pub struct PlaylistStore {
+    pub playlists: Vec<Playlist>,
+}
+
+fn playlists_path() -> PathBuf {
+    data_dir().join("playlists.json")
+}
+
+impl PlaylistStore {
+    pub fn load() -> PlaylistStore {
+        let json = fs::read_to_string(playlists_path())
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+            .unwrap_or(Value::Null);
+        let playlists = json
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| {
+                        Some(Playlist {
+                            name: item["name"].as_str()?.to_string(),
+                            tracks: item["tracks"]
+                                .as_array()
+                                .map(|tracks| tracks.iter().filter_map(SearchResult::from_json).collect())
+                                .unwrap_or_default(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        PlaylistStore { playlists }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let items: Vec<Value> = self.playlists
+            .iter()
+            .map(|playlist| {
+                let tracks: Vec<Value> = playlist.tracks.iter().map(SearchResult::to_json).collect();
+                json!({ "name": playlist.name, "tracks": tracks })
+            })
+            .collect();
+        let path = playlists_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&Value::Array(items))?)
+    }
+
+    /// Adds a playlist, numbering the name if it is taken. Returns its index.
+    pub fn create(&mut self, name: &str, tracks: Vec<SearchResult>) -> usize {
+        let name = if name.trim().is_empty() { "New Playlist" } else { name.trim() };
+        let mut unique = name.to_string();
+        let mut n = 2;
+        while self.playlists.iter().any(|p| p.name == unique) {
+            unique = format!("{} ({})", name, n);
+            n += 1;
+        }
+        self.playlists.push(Playlist { name: unique, tracks });
+        self.playlists.len() - 1
+    }
+}
+
+/// Turns a playlist location back into a track. Relative paths are resolved
+/// against the folder of the playlist file.
+pub fn track_from_location(
+    location: &str,
+    title: Option<String>,
+    base_dir: &Path,
+    sources: &SourceRegistry
+) -> Option<SearchResult> {
+    let location = location.trim();
+    let mut track = match sources.track_from_url(location) {
+        Some(track) => track,
+        None if location.contains("://") && !location.starts_with("file://") => {
+            return None;
+        }
+        None => {
+            let path = location_path(location);
+            let path = if path.is_relative() { base_dir.join(path) } else { path };
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            SearchResult {
+                identifier: path.to_string_lossy().to_string(),
+                title: file_name,
+                artist: None,
+                year: None,
+                duration: None,
+                source: LOCAL.to_string(),
+            }
+        }
+    };
+    if let Some(title) = title {
+        track.title = title;
+    }
+    Some(track)
+}
+
+// `file://` URIs (what XSPF files from other players hold) are
+// percent-decoded, anything else is taken as a path as it is
+fn location_path(location: &str) -> PathBuf {
+    if location.starts_with("file://") {
+        if let Some(path) = Url::parse(location).ok().and_then(|url| url.to_file_path().ok()) {
+            return path;
+        }
+    }
+    PathBuf::from(location.trim_start_matches("file://"))
+}
+
+// XSPF locations are URIs, local files go out as percent-encoded `file://` ones
+fn xspf_location(location: String) -> String {
+    if !Path::new(&location).is_absolute() {
+        return location;
+    }
+    Url::from_file_path(&location)
+        .map(|url| url.to_string())
+        .unwrap_or(location)
+}
+
+// Relative XSPF locations are URI references too, so they are percent-decoded
+// against the folder of the playlist file
+fn xspf_relative(location: String, base_dir: &Path) -> String {
+    if location.contains("://") {
+        return location;
+    }
+    let base_dir = std::env::current_dir()
+        .map(|dir| dir.join(base_dir))
+        .unwrap_or_else(|_| base_dir.to_path_buf());
+    Url::from_directory_path(base_dir)
+        .ok()
+        .and_then(|base| base.join(&location).ok())
+        .filter(|url| url.scheme() == "file")
+        .map(|url| url.to_string())
+        .unwrap_or(location)
+}
+
+pub fn export_m3u8(playlist: &Playlist, sources: &SourceRegistry) -> String {
+    let mut output = format!("#EXTM3U\n#PLAYLIST:{}\n", playlist.name);
+    for track in &playlist.tracks {
+        let duration = track.duration.map(|d| d as i64).unwrap_or(-1);
+        let name = match &track.artist {
+            Some(artist) => format!("{} - {}", artist, track.title),
+            None => track.title.clone(),
+        };
+        output.push_str(&format!("#EXTINF:{},{}\n{}\n", duration, name, sources.location(track)));
+    }
+    output
+}
+
+pub fn export_xspf(playlist: &Playlist, sources: &SourceRegistry) -> String {
+    let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    output.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    output.push_str(&format!("  <title>{}</title>\n  <trackList>\n", xml::escape(&playlist.name)));
+    for track in &playlist.tracks {
+        output.push_str("    <track>\n");
+        output.push_str(&format!("      <location>{}</location>\n", xml::escape(&xspf_location(sources.location(track)))));
+        output.push_str(&format!("      <title>{}</title>\n", xml::escape(&track.title)));
+        if let Some(artist) = &track.artist {
+            output.push_str(&format!("      <creator>{}</creator>\n", xml::escape(artist)));
+        }
+        if let Some(duration) = track.duration {
+            // XSPF durations are in milliseconds
+            output.push_str(&format!("      <duration>{}</duration>\n", duration * 1000));
+        }
+        output.push_str("    </track>\n");
+    }
+    output.push_str("  </trackList>\n</playlist>\n");
+    output
+}
+
+fn import_m3u(
+    contents: &str,
+    base_dir: &Path,
+    sources: &SourceRegistry
+) -> (Option<String>, Vec<SearchResult>) {
+    let mut name = None;
+    let mut tracks = Vec::new();
+    // Duration and display name from the #EXTINF line before each location
+    let mut pending: Option<(Option<u64>, String)> = None;
+    for line in contents.lines().map(str::trim) {
+        if let Some(title) = line.strip_prefix("#PLAYLIST:") {
+            name = Some(title.trim().to_string());
+        } else if let Some(info) = line.strip_prefix("#EXTINF:") {
+            let (duration, display) = info.split_once(',').unwrap_or((info, ""));
+            let duration = duration
+                .split_whitespace()
+                .next()
+                .and_then(|d| d.parse::<i64>().ok())
+                .filter(|d| *d >= 0)
+                .map(|d| d as u64);
+            pending = Some((duration, display.trim().to_string()));
+        } else if line.is_empty() || line.starts_with('#') {
+            continue;
+        } else {
+            let (duration, display) = pending.take().unwrap_or((None, String::new()));
+            // "Artist - Title" is the usual convention for the display name
+            let (artist, title) = match display.split_once(" - ") {
+                Some((artist, title)) => (Some(artist.to_string()), Some(title.to_string())),
+                None if !display.is_empty() => (None, Some(display)),
+                None => (None, None),
+            };
+            if let Some(mut track) = track_from_location(line, title, base_dir, sources) {
+                track.artist = artist;
+                track.duration = duration;
+                tracks.push(track);
+            }
+        }
+    }
+    (name, tracks)
+}
+
+fn import_xspf(
+    contents: &str,
+    base_dir: &Path,
+    sources: &SourceRegistry
+) -> (Option<String>, Vec<SearchResult>) {
+    // The playlist title is the one outside of the track list
+    let header = contents.split("<trackList").next().unwrap_or("");
+    let name = xml::text(header, "title");
+    let tracks = xml::elements(contents, "track")
+        .into_iter()
+        .filter_map(|(_, body)| {
+            let location = xspf_relative(xml::text(body, "location")?, base_dir);
+            let mut track = track_from_location(&location, xml::text(body, "title"), base_dir, sources)?;
+            track.artist = xml::text(body, "creator");
+            track.duration = xml::text(body, "duration")
+                .and_then(|d| d.parse::<u64>().ok())
+                .map(|ms| ms / 1000);
+            Some(track)
+        })
+        .collect();
+    (name, tracks)
+}
+
+/// Reads an M3U, M3U8 or XSPF file. Entries that point nowhere pjplayer can
+/// play are left out. The playlist is named after the file when it has no title.
+pub fn import_playlist(path: &Path, sources: &SourceRegistry) -> Result<Playlist, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    let is_xspf = path
+        .extension()
+        .map(|e| e.eq_ignore_ascii_case("xspf"))
+        .unwrap_or(false);
+    let (name, tracks) = if is_xspf {
+        import_xspf(&contents, base_dir, sources)
+    } else {
+        import_m3u(&contents, base_dir, sources)
+    };
+    let name = name.unwrap_or_else(|| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+    Ok(Playlist { name, tracks })
+}
+
+/// Writes XSPF when the path ends in `.xspf`, M3U8 otherwise
+pub fn export_playlist(
+    playlist: &Playlist,
+    path: &Path,
+    sources: &SourceRegistry
+) -> Result<(), Box<dyn Error>> {
+    let is_xspf = path
+        .extension()
+        .map(|e| e.eq_ignore_ascii_case("xspf"))
+        .unwrap_or(false);
+    let contents = if is_xspf {
+        export_xspf(playlist, sources)
+    } else {
+        export_m3u8(playlist, sources)
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_local_files_as_file_uris() {
+        assert_eq!(
+            xspf_location("/home/u/My Music/a b#1.flac".to_string()),
+            "file:///home/u/My%20Music/a%20b%231.flac"
+        );
+        assert_eq!(xspf_location("https://example.com/a".to_string()), "https://example.com/a");
+    }
+
+    #[test]
+    fn decodes_file_uris() {
+        assert_eq!(location_path("file:///home/u/My%20Music/a%20b.flac"), PathBuf::from("/home/u/My Music/a b.flac"));
+        assert_eq!(location_path("/home/u/100% real.mp3"), PathBuf::from("/home/u/100% real.mp3"));
+        assert_eq!(location_path("music/a.mp3"), PathBuf::from("music/a.mp3"));
+    }
+
+    #[test]
+    fn round_trips_local_paths() {
+        let path = "/home/u/Ünïcode & more/track (1).ogg";
+        assert_eq!(location_path(&xspf_location(path.to_string())), PathBuf::from(path));
+    }
+
+    #[test]
+    fn resolves_relative_xspf_locations() {
+        let location = xspf_relative("My%20Music/a%20b.flac".to_string(), Path::new("/home/u"));
+        assert_eq!(location_path(&location), PathBuf::from("/home/u/My Music/a b.flac"));
+        assert_eq!(xspf_relative("https://example.com/a".to_string(), Path::new("/home/u")), "https://example.com/a");
+    }
+}
@@ -0,0 +1,222 @@
+// plugins.rs
+// External sources: executables in the plugin folder that answer JSON
+// requests. Every request starts the plugin once, writes one JSON line to its
+// stdin and reads one JSON line back from its stdout. PLUGINS.md documents the
+// protocol.
+use std::fs;
+use std::io::{ BufRead, BufReader, Write };
+use std::os::unix::fs::PermissionsExt;
+use std::path::{ Path, PathBuf };
+use std::process::{ Command, Stdio };
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use serde_json::{ json, Value };
+use crate::app::SearchResult;
+use crate::config::Config;
+use crate::download::{ fetch_direct, fetch_with_yt_dlp, DownloadOptions, Downloaded };
+use crate::history::DownloadHistory;
+use crate::sources::{ MusicSource, SourceResult, Stream };
+
+// How long a plugin gets to answer before it is killed
+const INFO_TIMEOUT: Duration = Duration::from_secs(5);
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(30);
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(15);
+
+pub struct PluginSource {
+    path: PathBuf,
+    key: String,
+    label: String,
+    can_download: bool,
+    // Links starting with one of these are listed with "list_tracks"
+    collections: Vec<String>,
+}
+
+/// Every executable in `dir` that answers the "info" request. Plugins that
+/// fail to answer are left out.
+pub fn discover_plugins(dir: &Path) -> Vec<PluginSource> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            fs::metadata(path)
+                .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        })
+        .collect();
+    paths.sort();
+
+    // Asked all at once, a slow plugin only delays the start by its own timeout
+    thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .into_iter()
+            .map(|path| scope.spawn(move || PluginSource::load(path)))
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().ok().flatten())
+            .collect()
+    })
+}
+
+impl PluginSource {
+    fn load(path: PathBuf) -> Option<PluginSource> {
+        let name = path.file_stem()?.to_string_lossy().to_string();
+        let info = call(&path, &json!({ "method": "info" }), INFO_TIMEOUT).ok()?;
+        Some(PluginSource {
+            key: format!("plugin:{}", name),
+            label: info["name"].as_str().unwrap_or(&name).to_string(),
+            can_download: info["download"].as_bool().unwrap_or(true),
+            collections: info["collections"]
+                .as_array()
+                .map(|prefixes| {
+                    prefixes
+                        .iter()
+                        .filter_map(|prefix| prefix.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            path,
+        })
+    }
+
+    fn request(&self, request: Value, timeout: Duration) -> SourceResult<Value> {
+        call(&self.path, &request, timeout).map_err(|e| format!("{}: {}", self.label, e).into())
+    }
+
+    fn tracks(&self, response: &Value) -> Vec<SearchResult> {
+        response["results"]
+            .as_array()
+            .map(|results| {
+                results
+                    .iter()
+                    .filter_map(|track| {
+                        Some(SearchResult {
+                            identifier: track["id"].as_str()?.to_string(),
+                            title: track["title"].as_str()?.to_string(),
+                            artist: track["artist"].as_str().map(String::from),
+                            year: track["year"].as_str().map(String::from),
+                            duration: track["duration"].as_f64().map(|d| d as u64),
+                            source: self.key.clone(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+// Runs the plugin for one request. The plugin is killed when it has not
+// answered within `timeout`, so a hanging plugin costs at most that long.
+fn call(path: &Path, request: &Value, timeout: Duration) -> SourceResult<Value> {
+    let mut child = Command::new(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // A plugin that exits without reading its request still gets to answer
+        let _ = writeln!(stdin, "{}", request);
+    }
+    let stdout = child.stdout.take().ok_or("plugin has no stdout")?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut line = String::new();
+        let result = BufReader::new(stdout)
+            .read_line(&mut line)
+            .map(|_| line);
+        let _ = sender.send(result);
+    });
+
+    let answer = receiver.recv_timeout(timeout);
+    let _ = child.kill();
+    let _ = child.wait();
+    let line = match answer {
+        Ok(line) => line?,
+        Err(_) => {
+            return Err(format!("no answer within {} seconds", timeout.as_secs()).into());
+        }
+    };
+    if line.trim().is_empty() {
+        return Err("no answer".into());
+    }
+
+    let response: Value = serde_json::from_str(&line).map_err(|e| format!("invalid answer: {}", e))?;
+    match response["error"].as_str() {
+        Some(error) => Err(error.to_string().into()),
+        None => Ok(response),
+    }
+}
+
+impl MusicSource for PluginSource {
+    fn key(&self) -> &str {
+        &self.key
+    }
+
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        let response = self.request(json!({ "method": "search", "query": query }), SEARCH_TIMEOUT)?;
+        Ok(self.tracks(&response))
+    }
+
+    fn is_collection(&self, query: &str) -> bool {
+        let query = query.trim();
+        self.collections.iter().any(|prefix| query.starts_with(prefix.as_str()))
+    }
+
+    fn list_tracks(&self, url: &str) -> SourceResult<(String, Vec<SearchResult>)> {
+        let response = self.request(
+            json!({ "method": "list_tracks", "url": url.trim() }),
+            SEARCH_TIMEOUT
+        )?;
+        let title = response["title"].as_str().unwrap_or(&self.label).to_string();
+        Ok((title, self.tracks(&response)))
+    }
+
+    fn location(&self, track: &SearchResult) -> String {
+        track.identifier.clone()
+    }
+
+    fn resolve(&self, track: &SearchResult) -> SourceResult<Stream> {
+        let response = self.request(
+            json!({ "method": "resolve", "id": track.identifier }),
+            RESOLVE_TIMEOUT
+        )?;
+        let url = response["url"]
+            .as_str()
+            .ok_or_else(|| format!("{}: no url for {}", self.label, track.title))?
+            .to_string();
+        if response["extract"].as_bool().unwrap_or(false) {
+            Ok(Stream::Extract(url))
+        } else {
+            Ok(Stream::Direct(url))
+        }
+    }
+
+    fn can_download(&self) -> bool {
+        self.can_download
+    }
+
+    fn download(
+        &self,
+        track: &SearchResult,
+        options: DownloadOptions,
+        config: &Config,
+        history: &Mutex<DownloadHistory>,
+        playlist: Option<(&str, u32)>
+    ) -> SourceResult<Downloaded> {
+        match self.resolve(track)? {
+            Stream::Direct(url) => fetch_direct(track, &url, config, history),
+            Stream::Extract(url) => fetch_with_yt_dlp(track, &url, options, config, history, playlist),
+            Stream::Live(_) => Err("Live streams cannot be downloaded".into()),
+        }
+    }
+}
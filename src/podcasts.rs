@@ -0,0 +1,470 @@
+// podcasts.rs
+// Podcast subscriptions: RSS and Atom feeds, their episodes and which of
+// them have been played.
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{ Arc, Mutex };
+use serde_json::{ json, Value };
+use crate::app::SearchResult;
+use crate::config::{ data_dir, Config };
+use crate::download::{ fetch_direct, DownloadOptions, Downloaded };
+use crate::history::DownloadHistory;
+use crate::sources::{ MusicSource, SourceResult, Stream, PODCAST };
+use crate::xml;
+
+#[derive(Debug, Clone)]
+pub struct Episode {
+    // The feed's guid, or the enclosure URL when it has none
+    pub guid: String,
+    pub title: String,
+    pub url: String,
+    // As shown, "10 Jun 2024"
+    pub published: Option<String>,
+    pub duration: Option<u64>,
+    pub played: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Podcast {
+    pub feed_url: String,
+    pub title: String,
+    // Newest first, the way feeds list them
+    pub episodes: Vec<Episode>,
+    // Playback speed for this feed's episodes, when it differs from the source's
+    pub speed: Option<f64>,
+}
+
+impl Podcast {
+    pub fn unplayed(&self) -> usize {
+        self.episodes
+            .iter()
+            .filter(|episode| !episode.played)
+            .count()
+    }
+
+    pub fn episode_result(&self, episode: &Episode) -> SearchResult {
+        SearchResult {
+            identifier: episode.url.clone(),
+            title: episode.title.clone(),
+            artist: Some(self.title.clone()),
+            year: episode.published
+                .as_deref()
+                .and_then(|date| date.split(' ').next_back())
+                .map(String::from),
+            duration: episode.duration,
+            source: PODCAST.to_string(),
+        }
+    }
+}
+
+/// Subscribed feeds, persisted as `podcasts.json` in the data directory
+#[derive(Debug, Default)]
+pub struct PodcastStore {
+    pub podcasts: Vec<Podcast>,
+}
+
+fn podcasts_path() -> PathBuf {
+    data_dir().join("podcasts.json")
+}
+
+impl PodcastStore {
+    pub fn load() -> PodcastStore {
+        let json = fs::read_to_string(podcasts_path())
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+            .unwrap_or(Value::Null);
+        let podcasts = json
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| {
+                        Some(Podcast {
+                            feed_url: item["feed_url"].as_str()?.to_string(),
+                            title: item["title"].as_str()?.to_string(),
+                            speed: item["speed"].as_f64(),
+                            episodes: item["episodes"]
+                                .as_array()
+                                .map(|episodes| episodes.iter().filter_map(episode_from_json).collect())
+                                .unwrap_or_default(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        PodcastStore { podcasts }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let items: Vec<Value> = self.podcasts
+            .iter()
+            .map(|podcast| {
+                let episodes: Vec<Value> = podcast.episodes
+                    .iter()
+                    .map(|episode| {
+                        json!({
+                            "guid": episode.guid,
+                            "title": episode.title,
+                            "url": episode.url,
+                            "published": episode.published,
+                            "duration": episode.duration,
+                            "played": episode.played,
+                        })
+                    })
+                    .collect();
+                json!({
+                    "feed_url": podcast.feed_url,
+                    "title": podcast.title,
+                    "speed": podcast.speed,
+                    "episodes": episodes,
+                })
+            })
+            .collect();
+        let path = podcasts_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&Value::Array(items))?)
+    }
+
+    pub fn position(&self, feed_url: &str) -> Option<usize> {
+        self.podcasts.iter().position(|podcast| podcast.feed_url == feed_url)
+    }
+
+    /// Puts a freshly fetched feed in place of the stored one, or adds it.
+    /// Played marks carry over to the episodes that are still listed, and
+    /// the feed keeps its speed.
+    pub fn update(&mut self, mut podcast: Podcast) {
+        match self.position(&podcast.feed_url) {
+            Some(index) => {
+                let old = &self.podcasts[index];
+                podcast.speed = old.speed;
+                for episode in &mut podcast.episodes {
+                    episode.played = old.episodes
+                        .iter()
+                        .any(|e| e.played && e.guid == episode.guid);
+                }
+                self.podcasts[index] = podcast;
+            }
+            None => self.podcasts.push(podcast),
+        }
+    }
+
+    /// Speed set for the feed that lists this enclosure URL
+    pub fn speed_for(&self, url: &str) -> Option<f64> {
+        self.podcasts
+            .iter()
+            .find(|podcast| podcast.episodes.iter().any(|episode| episode.url == url))
+            .and_then(|podcast| podcast.speed)
+    }
+
+    /// Marks every episode with this enclosure URL, returns true if there was one
+    pub fn set_played(&mut self, url: &str, played: bool) -> bool {
+        let mut found = false;
+        for episode in self.podcasts
+            .iter_mut()
+            .flat_map(|podcast| podcast.episodes.iter_mut())
+            .filter(|episode| episode.url == url) {
+            episode.played = played;
+            found = true;
+        }
+        found
+    }
+}
+
+fn episode_from_json(item: &Value) -> Option<Episode> {
+    Some(Episode {
+        guid: item["guid"].as_str()?.to_string(),
+        title: item["title"].as_str()?.to_string(),
+        url: item["url"].as_str()?.to_string(),
+        published: item["published"].as_str().map(String::from),
+        duration: item["duration"].as_u64(),
+        played: item["played"].as_bool().unwrap_or(false),
+    })
+}
+
+/// Downloads and parses a feed. Blocking.
+pub fn fetch_feed(feed_url: &str) -> Result<Podcast, Box<dyn Error + Send + Sync>> {
+    let client = reqwest::blocking::Client::new();
+    let body = client.get(feed_url).send()?.error_for_status()?.text()?;
+    parse_feed(feed_url, &body).ok_or_else(|| format!("{} is not an RSS or Atom feed", feed_url).into())
+}
+
+fn parse_feed(feed_url: &str, body: &str) -> Option<Podcast> {
+    if let Some((_, channel)) = xml::elements(body, "channel").into_iter().next() {
+        // The channel title is the one before the first item
+        let header = channel.split("<item").next().unwrap_or("");
+        let episodes = xml::elements(channel, "item")
+            .into_iter()
+            .filter_map(|(_, item)| {
+                let (attributes, _) = xml::elements(item, "enclosure").into_iter().next()?;
+                let url = xml::attribute(attributes, "url")?;
+                Some(Episode {
+                    guid: xml::text(item, "guid").unwrap_or_else(|| url.clone()),
+                    title: xml::text(item, "title").unwrap_or_else(|| url.clone()),
+                    published: xml::text(item, "pubDate").and_then(|date| rss_date(&date)),
+                    duration: xml::text(item, "itunes:duration").and_then(|d| parse_duration(&d)),
+                    played: false,
+                    url,
+                })
+            })
+            .collect();
+        return Some(Podcast {
+            feed_url: feed_url.to_string(),
+            title: xml::text(header, "title").unwrap_or_else(|| feed_url.to_string()),
+            episodes,
+            speed: None,
+        });
+    }
+
+    let (_, feed) = xml::elements(body, "feed").into_iter().next()?;
+    let header = feed.split("<entry").next().unwrap_or("");
+    let episodes = xml::elements(feed, "entry")
+        .into_iter()
+        .filter_map(|(_, entry)| {
+            let url = xml::elements(entry, "link")
+                .into_iter()
+                .find(|(attributes, _)| xml::attribute(attributes, "rel").as_deref() == Some("enclosure"))
+                .and_then(|(attributes, _)| xml::attribute(attributes, "href"))?;
+            Some(Episode {
+                guid: xml::text(entry, "id").unwrap_or_else(|| url.clone()),
+                title: xml::text(entry, "title").unwrap_or_else(|| url.clone()),
+                published: xml::text(entry, "published")
+                    .or_else(|| xml::text(entry, "updated"))
+                    .and_then(|date| atom_date(&date)),
+                duration: None,
+                played: false,
+                url,
+            })
+        })
+        .collect();
+    Some(Podcast {
+        feed_url: feed_url.to_string(),
+        title: xml::text(header, "title").unwrap_or_else(|| feed_url.to_string()),
+        episodes,
+        speed: None,
+    })
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// "Mon, 10 Jun 2024 04:00:00 GMT" becomes "10 Jun 2024". Anything that does
+// not read as a day, a month and a year is no date at all.
+fn rss_date(date: &str) -> Option<String> {
+    let date = date.split_once(',').map(|(_, rest)| rest).unwrap_or(date);
+    let mut parts = date.split_whitespace();
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = parts.next()?.get(..3)?;
+    let year = parts.next()?;
+    let month = MONTHS.iter().find(|name| name.eq_ignore_ascii_case(month))?;
+    if !(1..=31).contains(&day) || year.len() != 4 || !year.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!("{} {} {}", day, month, year))
+}
+
+// "2024-06-10T04:00:00Z" becomes "10 Jun 2024"
+fn atom_date(date: &str) -> Option<String> {
+    let mut parts = date.get(..10)?.split('-');
+    let year = parts.next()?;
+    let month: usize = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if !(1..=31).contains(&day) || year.len() != 4 || !year.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!("{} {} {}", day, MONTHS.get(month.checked_sub(1)?)?, year))
+}
+
+// itunes:duration is either seconds or [HH:]MM:SS
+fn parse_duration(duration: &str) -> Option<u64> {
+    duration
+        .trim()
+        .split(':')
+        .try_fold(0u64, |total, part| Some(total * 60 + part.trim().parse::<f64>().ok()? as u64))
+}
+
+// Episodes are kept by their enclosure URL
+pub struct PodcastSource {
+    podcasts: Arc<Mutex<PodcastStore>>,
+}
+
+impl PodcastSource {
+    pub fn new(podcasts: Arc<Mutex<PodcastStore>>) -> PodcastSource {
+        PodcastSource { podcasts }
+    }
+}
+
+impl MusicSource for PodcastSource {
+    fn key(&self) -> &str {
+        PODCAST
+    }
+
+    fn label(&self) -> &str {
+        "Podcasts"
+    }
+
+    // Searches the episodes of the subscribed feeds
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        let query = query.trim().to_lowercase();
+        let podcasts = self.podcasts.lock().unwrap();
+        Ok(
+            podcasts.podcasts
+                .iter()
+                .flat_map(|podcast| {
+                    podcast.episodes
+                        .iter()
+                        .filter(|episode| {
+                            episode.title.to_lowercase().contains(&query) ||
+                                podcast.title.to_lowercase().contains(&query)
+                        })
+                        .map(|episode| podcast.episode_result(episode))
+                })
+                .collect()
+        )
+    }
+
+    fn location(&self, track: &SearchResult) -> String {
+        track.identifier.clone()
+    }
+
+    fn resolve(&self, track: &SearchResult) -> SourceResult<Stream> {
+        Ok(Stream::Direct(track.identifier.clone()))
+    }
+
+    // Episodes of the subscriptions are searched as they were last fetched
+    fn works_offline(&self) -> bool {
+        true
+    }
+
+    fn download(
+        &self,
+        track: &SearchResult,
+        _options: DownloadOptions,
+        config: &Config,
+        history: &Mutex<DownloadHistory>,
+        _playlist: Option<(&str, u32)>
+    ) -> SourceResult<Downloaded> {
+        fetch_direct(track, &track.identifier, config, history)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSS: &str =
+        r#"<?xml version="1.0"?>
+<rss xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd"><channel>
+  <title><![CDATA[Night & Day]]></title>
+  <item>
+    <title><![CDATA[Episode <1>]]></title>
+    <guid>ep-1</guid>
+    <pubDate>Mon, 10 Jun 2024 04:00:00 GMT</pubDate>
+    <itunes:duration>1:02:03</itunes:duration>
+    <enclosure url="https://example.com/1.mp3?a=1&amp;b=2" type="audio/mpeg" length="1"/>
+  </item>
+  <item>
+    <title>No audio</title>
+  </item>
+  <item>
+    <title>Second</title>
+    <pubDate>sometime last week</pubDate>
+    <itunes:duration>95</itunes:duration>
+    <enclosure url='https://example.com/2.mp3' />
+  </item>
+</channel></rss>"#;
+
+    const ATOM: &str =
+        r#"<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Atom Show</title>
+  <entry>
+    <title>First</title>
+    <id>tag:example.com,2024:1</id>
+    <published>2024-06-10T04:00:00Z</published>
+    <link rel="alternate" href="https://example.com/1"/>
+    <link rel="enclosure" hreflang="en" href="https://example.com/1.ogg"/>
+  </entry>
+  <entry>
+    <title>Page only</title>
+    <link href="https://example.com/2"/>
+  </entry>
+  <entry>
+    <title>Bad date</title>
+    <updated>2024-13-45T00:00:00Z</updated>
+    <link rel="enclosure" href="https://example.com/3.ogg"></link>
+  </entry>
+</feed>"#;
+
+    #[test]
+    fn parses_rss_channels() {
+        let podcast = parse_feed("https://example.com/feed", RSS).unwrap();
+        assert_eq!(podcast.title, "Night & Day");
+        assert_eq!(podcast.episodes.len(), 2);
+
+        let first = &podcast.episodes[0];
+        assert_eq!(first.title, "Episode <1>");
+        assert_eq!(first.guid, "ep-1");
+        assert_eq!(first.url, "https://example.com/1.mp3?a=1&b=2");
+        assert_eq!(first.published.as_deref(), Some("10 Jun 2024"));
+        assert_eq!(first.duration, Some(3723));
+
+        let second = &podcast.episodes[1];
+        assert_eq!(second.url, "https://example.com/2.mp3");
+        assert_eq!(second.guid, second.url);
+        assert_eq!(second.published, None);
+        assert_eq!(second.duration, Some(95));
+    }
+
+    #[test]
+    fn parses_atom_feeds() {
+        let podcast = parse_feed("https://example.com/atom", ATOM).unwrap();
+        assert_eq!(podcast.title, "Atom Show");
+        assert_eq!(podcast.episodes.len(), 2);
+        assert_eq!(podcast.episodes[0].url, "https://example.com/1.ogg");
+        assert_eq!(podcast.episodes[0].guid, "tag:example.com,2024:1");
+        assert_eq!(podcast.episodes[0].published.as_deref(), Some("10 Jun 2024"));
+        assert_eq!(podcast.episodes[1].url, "https://example.com/3.ogg");
+        assert_eq!(podcast.episodes[1].published, None);
+    }
+
+    #[test]
+    fn rejects_documents_that_are_not_feeds() {
+        assert!(parse_feed("https://example.com", "<html><body>Hello</body></html>").is_none());
+        assert!(parse_feed("https://example.com", "").is_none());
+    }
+
+    #[test]
+    fn reads_rss_dates() {
+        assert_eq!(rss_date("Mon, 10 Jun 2024 04:00:00 GMT").as_deref(), Some("10 Jun 2024"));
+        assert_eq!(rss_date("3 june 2021 10:00 +0200").as_deref(), Some("3 Jun 2021"));
+        assert_eq!(rss_date("sometime last week"), None);
+        assert_eq!(rss_date("Mon, 40 Jun 2024"), None);
+        assert_eq!(rss_date("10 Jun"), None);
+        assert_eq!(rss_date("2024-06-10"), None);
+        assert_eq!(rss_date(""), None);
+    }
+
+    #[test]
+    fn reads_atom_dates() {
+        assert_eq!(atom_date("2024-06-10T04:00:00Z").as_deref(), Some("10 Jun 2024"));
+        assert_eq!(atom_date("2024-12-01").as_deref(), Some("1 Dec 2024"));
+        assert_eq!(atom_date("2024-13-01"), None);
+        assert_eq!(atom_date("2024-00-01"), None);
+        assert_eq!(atom_date("2024-06-1x"), None);
+        assert_eq!(atom_date("Mon, 10 Jun 2024"), None);
+        assert_eq!(atom_date("2024"), None);
+    }
+
+    #[test]
+    fn reads_durations() {
+        assert_eq!(parse_duration("95"), Some(95));
+        assert_eq!(parse_duration("12:34"), Some(754));
+        assert_eq!(parse_duration("1:02:03"), Some(3723));
+        assert_eq!(parse_duration(" 01:00:00 "), Some(3600));
+        assert_eq!(parse_duration("42.7"), Some(42));
+        assert_eq!(parse_duration("1::3"), None);
+        assert_eq!(parse_duration("an hour"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+}
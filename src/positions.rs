@@ -0,0 +1,86 @@
+// positions.rs
+// Where playback of long tracks stopped, so podcasts, lectures and
+// audiobooks can pick up from there the next time they are played.
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use serde_json::{ Map, Value };
+use crate::app::SearchResult;
+use crate::config::{ data_dir, Config };
+
+// Stopping this early is not worth offering to resume
+const MIN_POSITION: u64 = 30;
+
+/// Positions in seconds by source and identifier, persisted as
+/// `positions.json` in the data directory
+#[derive(Debug, Default)]
+pub struct PlaybackPositions {
+    positions: HashMap<String, u64>,
+}
+
+fn positions_path() -> PathBuf {
+    data_dir().join("positions.json")
+}
+
+fn key(track: &SearchResult) -> String {
+    format!("{}:{}", track.source, track.identifier)
+}
+
+impl PlaybackPositions {
+    pub fn load() -> PlaybackPositions {
+        let positions = fs::read_to_string(positions_path())
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+            .and_then(|json| {
+                json.as_object().map(|items| {
+                    items
+                        .iter()
+                        .filter_map(|(key, position)| Some((key.clone(), position.as_u64()?)))
+                        .collect()
+                })
+            })
+            .unwrap_or_default();
+        PlaybackPositions { positions }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let items: Map<String, Value> = self.positions
+            .iter()
+            .map(|(key, position)| (key.clone(), Value::from(*position)))
+            .collect();
+        let path = positions_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&Value::Object(items))?)
+    }
+
+    pub fn get(&self, track: &SearchResult) -> Option<u64> {
+        self.positions.get(&key(track)).copied()
+    }
+
+    /// Remembers where `track` stopped. Short tracks are left out, and a
+    /// track stopped past `resume_finished_percent` counts as finished, so
+    /// its position is dropped. Returns true when something changed.
+    pub fn remember(&mut self, track: &SearchResult, position: u64, config: &Config) -> bool {
+        // Without a known length, listening long enough makes a track long
+        if track.duration.unwrap_or(position) < config.resume_min_length {
+            return false;
+        }
+        let finished = track.duration.is_some_and(|duration| {
+            position * 100 >= duration * config.resume_finished_percent
+        });
+        if finished {
+            return self.forget(track);
+        }
+        if position < MIN_POSITION {
+            return false;
+        }
+        self.positions.insert(key(track), position);
+        true
+    }
+
+    pub fn forget(&mut self, track: &SearchResult) -> bool {
+        self.positions.remove(&key(track)).is_some()
+    }
+}
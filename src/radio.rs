@@ -0,0 +1,270 @@
+// radio.rs
+// Internet radio: stations from a radio-browser style directory, favorite
+// stations, and the ICY metadata Icecast and Shoutcast servers mix into the
+// audio to announce the current song.
+use std::error::Error;
+use std::fs;
+use std::io::{ Read, Write };
+use std::path::PathBuf;
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
+use serde_json::Value;
+use crate::app::SearchResult;
+use crate::config::{ data_dir, Config };
+use crate::download::{ DownloadOptions, Downloaded };
+use crate::history::DownloadHistory;
+use crate::recorder::StreamRecorder;
+use crate::sources::{ MusicSource, SourceResult, Stream, RADIO };
+
+pub const DEFAULT_DIRECTORY_URL: &str = "https://de1.api.radio-browser.info";
+
+// radio-browser asks clients to identify themselves
+const USER_AGENT: &str = "pjplayer";
+
+/// Favorite stations, persisted as `radio_favorites.json` in the data directory
+#[derive(Debug, Default)]
+pub struct RadioFavorites {
+    pub stations: Vec<SearchResult>,
+}
+
+fn favorites_path() -> PathBuf {
+    data_dir().join("radio_favorites.json")
+}
+
+impl RadioFavorites {
+    pub fn load() -> RadioFavorites {
+        let stations = fs::read_to_string(favorites_path())
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+            .and_then(|json| {
+                json.as_array().map(|items| items.iter().filter_map(SearchResult::from_json).collect())
+            })
+            .unwrap_or_default();
+        RadioFavorites { stations }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let items: Vec<Value> = self.stations.iter().map(SearchResult::to_json).collect();
+        let path = favorites_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&Value::Array(items))?)
+    }
+
+    pub fn contains(&self, station: &SearchResult) -> bool {
+        self.stations.iter().any(|s| s.identifier == station.identifier)
+    }
+
+    /// Adds the station, or removes it when it is already a favorite. Returns
+    /// true when it was added.
+    pub fn toggle(&mut self, station: &SearchResult) -> bool {
+        let added = !self.contains(station);
+        if added {
+            self.stations.push(station.clone());
+        } else {
+            self.stations.retain(|s| s.identifier != station.identifier);
+        }
+        added
+    }
+}
+
+// Stations are kept by their stream URL
+pub struct RadioSource {
+    directory_url: String,
+    favorites: Arc<Mutex<RadioFavorites>>,
+}
+
+impl RadioSource {
+    pub fn new(config: &Config, favorites: Arc<Mutex<RadioFavorites>>) -> RadioSource {
+        RadioSource {
+            directory_url: config.radio_directory_url.trim_end_matches('/').to_string(),
+            favorites,
+        }
+    }
+}
+
+impl MusicSource for RadioSource {
+    fn key(&self) -> &str {
+        RADIO
+    }
+
+    fn label(&self) -> &str {
+        "Internet Radio"
+    }
+
+    // An empty search lists the favorites
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        if query.trim().is_empty() {
+            return Ok(self.favorites.lock().unwrap().stations.clone());
+        }
+
+        let client = reqwest::blocking::Client::builder().user_agent(USER_AGENT).build()?;
+        let stations: Value = client
+            .get(format!("{}/json/stations/search", self.directory_url))
+            .query(
+                &[
+                    ("name", query.trim()),
+                    ("limit", "30"),
+                    ("hidebroken", "true"),
+                    ("order", "clickcount"),
+                    ("reverse", "true"),
+                ]
+            )
+            .send()?
+            .error_for_status()?
+            .json()?;
+
+        let results = stations
+            .as_array()
+            .map(|stations| {
+                stations
+                    .iter()
+                    .filter_map(|station| {
+                        let url = station["url_resolved"]
+                            .as_str()
+                            .filter(|url| !url.is_empty())
+                            .or_else(|| station["url"].as_str())?;
+                        Some(SearchResult {
+                            identifier: url.to_string(),
+                            title: station["name"].as_str()?.trim().to_string(),
+                            artist: None,
+                            year: None,
+                            duration: None,
+                            source: RADIO.to_string(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(results)
+    }
+
+    fn location(&self, track: &SearchResult) -> String {
+        track.identifier.clone()
+    }
+
+    fn resolve(&self, track: &SearchResult) -> SourceResult<Stream> {
+        Ok(Stream::Live(track.identifier.clone()))
+    }
+
+    fn can_download(&self) -> bool {
+        false
+    }
+
+    fn download(
+        &self,
+        _track: &SearchResult,
+        _options: DownloadOptions,
+        _config: &Config,
+        _history: &Mutex<DownloadHistory>,
+        _playlist: Option<(&str, u32)>
+    ) -> SourceResult<Downloaded> {
+        Err("Live radio cannot be downloaded".into())
+    }
+}
+
+/// Copies a radio stream into `sink` and `recorder` until either side hangs
+/// up. When the server sends ICY metadata it is cut out of the audio and the
+/// current song goes into `stream_title`.
+pub fn relay_stream(
+    url: &str,
+    mut sink: impl Write,
+    stream_title: Arc<Mutex<Option<String>>>,
+    recorder: Arc<Mutex<StreamRecorder>>
+) -> Result<(), Box<dyn Error>> {
+    // The default timeout would cut every station off after 30 seconds
+    let client = reqwest::blocking::Client
+        ::builder()
+        .user_agent(USER_AGENT)
+        .timeout(None::<Duration>)
+        .build()?;
+    let mut response = client.get(url).header("Icy-MetaData", "1").send()?.error_for_status()?;
+    if let Some(content_type) = response.headers().get("content-type").and_then(|value| value.to_str().ok()) {
+        recorder.lock().unwrap().set_content_type(content_type);
+    }
+    let metaint = response
+        .headers()
+        .get("icy-metaint")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .filter(|metaint| *metaint > 0);
+    let Some(metaint) = metaint else {
+        let mut audio = [0u8; 16384];
+        loop {
+            let n = response.read(&mut audio)?;
+            if n == 0 {
+                return Ok(());
+            }
+            sink.write_all(&audio[..n])?;
+            recorder.lock().unwrap().write(&audio[..n]);
+        }
+    };
+
+    // Every `metaint` bytes of audio are followed by one length byte (in
+    // units of 16 bytes) and that much metadata
+    let mut audio = vec![0u8; metaint];
+    loop {
+        response.read_exact(&mut audio)?;
+        sink.write_all(&audio)?;
+        recorder.lock().unwrap().write(&audio);
+        let mut length = [0u8; 1];
+        response.read_exact(&mut length)?;
+        if length[0] == 0 {
+            continue;
+        }
+        let mut metadata = vec![0u8; (length[0] as usize) * 16];
+        response.read_exact(&mut metadata)?;
+        if let Some(title) = icy_title(&String::from_utf8_lossy(&metadata)) {
+            recorder.lock().unwrap().new_title(&title);
+            *stream_title.lock().unwrap() = Some(title);
+        }
+    }
+}
+
+// "StreamTitle='Artist - Song';StreamUrl='';" padded with zero bytes
+fn icy_title(metadata: &str) -> Option<String> {
+    let start = metadata.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &metadata[start..];
+    // Titles may contain apostrophes, so the value ends at `';`, or at the
+    // closing quote before the null padding when it is the last field
+    let title = match rest.find("';") {
+        Some(end) => &rest[..end],
+        None => {
+            let rest = rest.trim_end_matches('\0');
+            rest.strip_suffix('\'').unwrap_or(rest)
+        }
+    };
+    let title = title.trim();
+    if title.is_empty() { None } else { Some(title.to_string()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_stream_titles() {
+        assert_eq!(icy_title("StreamTitle='Artist - Song';StreamUrl='';").as_deref(), Some("Artist - Song"));
+        assert_eq!(icy_title("StreamUrl='x';StreamTitle='Song';\0\0\0").as_deref(), Some("Song"));
+    }
+
+    #[test]
+    fn keeps_apostrophes_in_titles() {
+        assert_eq!(icy_title("StreamTitle='Don't Stop';").as_deref(), Some("Don't Stop"));
+    }
+
+    #[test]
+    fn reads_unterminated_titles() {
+        assert_eq!(icy_title("StreamTitle='Song'\0\0\0").as_deref(), Some("Song"));
+        assert_eq!(icy_title("StreamTitle='Song\0\0").as_deref(), Some("Song"));
+    }
+
+    #[test]
+    fn ignores_empty_or_missing_titles() {
+        assert_eq!(icy_title("StreamTitle='';"), None);
+        assert_eq!(icy_title("StreamTitle=' ';\0"), None);
+        assert_eq!(icy_title("StreamUrl='x';"), None);
+        assert_eq!(icy_title(""), None);
+    }
+}
@@ -0,0 +1,156 @@
+// recorder.rs
+// Recording the radio station that is playing: one file per song in the
+// download folder, split where the station announces the next title.
+use std::fs::{ self, File };
+use std::io::Write;
+use std::path::PathBuf;
+use std::thread;
+use crate::config::Config;
+use crate::download::target_path;
+use crate::tags::{ write_tags, TrackMetadata };
+
+// The song playing is kept from its start, so recording can begin halfway
+// through it. This is about half an hour at 128 kbit/s.
+const MAX_SONG_BYTES: usize = 32 * 1024 * 1024;
+
+pub struct StreamRecorder {
+    config: Config,
+    station: String,
+    extension: &'static str,
+    // Song the station announced last
+    title: Option<String>,
+    // Audio of that song heard so far
+    song: Vec<u8>,
+    recording: bool,
+    file: Option<(File, PathBuf)>,
+    /// Songs recorded so far
+    pub saved: Vec<PathBuf>,
+}
+
+impl StreamRecorder {
+    pub fn new(station: &str, config: &Config) -> StreamRecorder {
+        StreamRecorder {
+            config: config.clone(),
+            station: station.to_string(),
+            extension: "mp3",
+            title: None,
+            song: Vec::new(),
+            recording: false,
+            file: None,
+            saved: Vec::new(),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Picks the file extension from the stream's Content-Type
+    pub fn set_content_type(&mut self, content_type: &str) {
+        let content_type = content_type.to_lowercase();
+        self.extension = if content_type.contains("aac") {
+            "aac"
+        } else if content_type.contains("ogg") {
+            "ogg"
+        } else if content_type.contains("flac") {
+            "flac"
+        } else {
+            "mp3"
+        };
+    }
+
+    /// Starts recording, from the beginning of the song playing. Nothing
+    /// counts as recording when the file cannot be opened.
+    pub fn start(&mut self) -> Result<(), String> {
+        self.open_file()?;
+        self.recording = true;
+        Ok(())
+    }
+
+    /// Stops recording. What there is of the song playing is kept.
+    pub fn stop(&mut self) {
+        self.recording = false;
+        self.finish_file();
+    }
+
+    pub fn write(&mut self, audio: &[u8]) {
+        self.song.extend_from_slice(audio);
+        if self.song.len() > MAX_SONG_BYTES {
+            self.song.drain(..MAX_SONG_BYTES / 4);
+        }
+        if let Some((file, _)) = &mut self.file {
+            // A full disk ends the recording rather than the stream
+            if file.write_all(audio).is_err() {
+                self.file = None;
+                self.recording = false;
+            }
+        }
+    }
+
+    /// The station announced a song, which starts a new file when it is a
+    /// different one
+    pub fn new_title(&mut self, title: &str) {
+        if self.title.as_deref() == Some(title) {
+            return;
+        }
+        self.finish_file();
+        self.title = Some(title.to_string());
+        self.song.clear();
+        if self.recording {
+            let _ = self.open_file();
+        }
+    }
+
+    // Stations announce "Artist - Title", or only the title. Without any
+    // announcement the recording goes by the station's name.
+    fn metadata(&self) -> TrackMetadata {
+        let announced = self.title.clone().unwrap_or_else(|| self.station.clone());
+        let (artist, title) = match announced.split_once(" - ") {
+            Some((artist, title)) => (Some(artist.trim().to_string()), title.trim().to_string()),
+            None => (None, announced),
+        };
+        TrackMetadata {
+            title,
+            artist,
+            album: Some(self.station.clone()),
+            ..TrackMetadata::default()
+        }
+    }
+
+    fn open_file(&mut self) -> Result<(), String> {
+        if self.file.is_some() {
+            return Ok(());
+        }
+        let metadata = self.metadata();
+        let Some(path) = target_path(&self.config, &metadata, self.extension) else {
+            return Err(format!("{} already exists", metadata.title));
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut file = File::create(&path).map_err(|e| e.to_string())?;
+        file.write_all(&self.song).map_err(|e| e.to_string())?;
+        self.file = Some((file, path));
+        Ok(())
+    }
+
+    fn finish_file(&mut self) {
+        let Some((file, path)) = self.file.take() else {
+            return;
+        };
+        drop(file);
+        let metadata = self.metadata();
+        let tag_path = path.clone();
+        // Tagging rewrites the file, the stream should not wait for that
+        thread::spawn(move || {
+            let _ = write_tags(&tag_path, &metadata);
+        });
+        self.saved.push(path);
+    }
+}
+
+impl Drop for StreamRecorder {
+    fn drop(&mut self) {
+        self.finish_file();
+    }
+}
@@ -0,0 +1,384 @@
+// replaygain.rs
+// Loudness analysis of library and downloaded files, so tracks from
+// different sources play at the same volume. Gains follow ReplayGain 2.0:
+// the EBU R128 integrated loudness of a track (or a whole album) is measured
+// with ffmpeg and the gain is what brings it to -18 LUFS.
+use std::collections::{ HashMap, HashSet };
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::process::{ Command, Stdio };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::Duration;
+use serde_json::{ json, Value };
+use crate::config::data_dir;
+use crate::library::{ file_stamp, probe_track, Library, LibraryTrack };
+use crate::search::archive_field;
+use crate::tags::write_replaygain_tags;
+
+const FFMPEG_PATH: &str = "ffmpeg";
+const FFPROBE_PATH: &str = "ffprobe";
+
+const REFERENCE_LOUDNESS: f64 = -18.0;
+
+// How often the library is checked for files that have not been analyzed yet
+const ANALYSIS_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GainMode {
+    Track,
+    Album,
+    Off,
+}
+
+impl GainMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            GainMode::Track => "track",
+            GainMode::Album => "album",
+            GainMode::Off => "off",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<GainMode> {
+        match name.to_lowercase().as_str() {
+            "track" => Some(GainMode::Track),
+            "album" => Some(GainMode::Album),
+            "off" => Some(GainMode::Off),
+            _ => None,
+        }
+    }
+
+    pub fn next(&self) -> GainMode {
+        match self {
+            GainMode::Track => GainMode::Album,
+            GainMode::Album => GainMode::Off,
+            GainMode::Off => GainMode::Track,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GainEntry {
+    // Modification time and size of the file when it was analyzed
+    pub mtime: u64,
+    pub size: u64,
+    // Integrated loudness in LUFS and true peak as a linear sample value
+    pub loudness: f64,
+    pub peak: f64,
+    pub album_loudness: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+impl GainEntry {
+    pub fn track_gain(&self) -> f64 {
+        REFERENCE_LOUDNESS - self.loudness
+    }
+
+    pub fn album_gain(&self) -> Option<f64> {
+        self.album_loudness.map(|loudness| REFERENCE_LOUDNESS - loudness)
+    }
+
+    /// Gain in dB to play the file with. Album mode falls back to the track
+    /// gain for files that are not part of an album. The gain is lowered
+    /// where it would push the peak into clipping.
+    pub fn gain(&self, mode: GainMode) -> Option<f64> {
+        let (gain, peak) = match mode {
+            GainMode::Off => {
+                return None;
+            }
+            GainMode::Album => {
+                match (self.album_gain(), self.album_peak) {
+                    (Some(gain), Some(peak)) => (gain, peak),
+                    _ => (self.track_gain(), self.peak),
+                }
+            }
+            GainMode::Track => (self.track_gain(), self.peak),
+        };
+        let headroom = if peak > 0.0 { -20.0 * peak.log10() } else { gain };
+        Some(gain.min(headroom))
+    }
+}
+
+/// Analysis results by file path, persisted as `replaygain.json` in the data
+/// directory
+#[derive(Debug, Default)]
+pub struct GainCache {
+    entries: HashMap<PathBuf, GainEntry>,
+}
+
+fn cache_path() -> PathBuf {
+    data_dir().join("replaygain.json")
+}
+
+impl GainCache {
+    pub fn load() -> GainCache {
+        let entries = fs::read_to_string(cache_path())
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+            .and_then(|json| {
+                json.as_object().map(|items| {
+                    items
+                        .iter()
+                        .filter_map(|(path, item)| {
+                            Some((
+                                PathBuf::from(path),
+                                GainEntry {
+                                    mtime: item["mtime"].as_u64()?,
+                                    size: item["size"].as_u64()?,
+                                    loudness: item["loudness"].as_f64()?,
+                                    peak: item["peak"].as_f64()?,
+                                    album_loudness: item["album_loudness"].as_f64(),
+                                    album_peak: item["album_peak"].as_f64(),
+                                },
+                            ))
+                        })
+                        .collect()
+                })
+            })
+            .unwrap_or_default();
+        GainCache { entries }
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let items: serde_json::Map<String, Value> = self.entries
+            .iter()
+            .map(|(path, entry)| {
+                (
+                    path.to_string_lossy().to_string(),
+                    json!({
+                        "mtime": entry.mtime,
+                        "size": entry.size,
+                        "loudness": entry.loudness,
+                        "peak": entry.peak,
+                        "album_loudness": entry.album_loudness,
+                        "album_peak": entry.album_peak,
+                    }),
+                )
+            })
+            .collect();
+        let path = cache_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(&Value::Object(items))?)
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&GainEntry> {
+        self.entries.get(path)
+    }
+
+    // Analyzed, and the file has not changed since
+    fn is_current(&self, path: &Path) -> bool {
+        match (self.entries.get(path), file_stamp(path)) {
+            (Some(entry), Some((mtime, size))) => entry.mtime == mtime && entry.size == size,
+            _ => false,
+        }
+    }
+}
+
+/// Keeps analyzing in the background: every library track, plus downloaded
+/// files outside the library folders, once each and again when they change.
+pub fn spawn_analysis(
+    library: Arc<Mutex<Library>>,
+    downloads: Vec<PathBuf>,
+    cache: Arc<Mutex<GainCache>>,
+    write_tags: bool
+) {
+    thread::spawn(move || {
+        // Downloads outside the library are not probed by anything else
+        let indexed: HashSet<PathBuf> = library
+            .lock()
+            .unwrap()
+            .tracks.iter()
+            .map(|track| track.path.clone())
+            .collect();
+        let outside: Vec<LibraryTrack> = downloads
+            .iter()
+            .filter(|path| !indexed.contains(*path))
+            .filter_map(|path| probe_track(path))
+            .collect();
+        // Files ffmpeg could not read, by their stamp, so they are not retried every round
+        let mut failed: HashSet<(PathBuf, Option<(u64, u64)>)> = HashSet::new();
+        loop {
+            let mut tracks = library.lock().unwrap().tracks.clone();
+            tracks.extend(outside.iter().cloned());
+            analyze(&tracks, &cache, write_tags, &mut failed);
+            thread::sleep(ANALYSIS_INTERVAL);
+        }
+    });
+}
+
+// Tracks in the same folder with the same album tag form an album
+fn album_key(track: &LibraryTrack) -> Option<(PathBuf, String)> {
+    let album = track.album.clone()?;
+    Some((track.path.parent()?.to_path_buf(), album))
+}
+
+fn analyze(
+    tracks: &[LibraryTrack],
+    cache: &Mutex<GainCache>,
+    write_tags: bool,
+    failed: &mut HashSet<(PathBuf, Option<(u64, u64)>)>
+) {
+    let pending: Vec<&LibraryTrack> = tracks
+        .iter()
+        .filter(|track| track.path.exists() && !cache.lock().unwrap().is_current(&track.path))
+        .filter(|track| !failed.contains(&(track.path.clone(), file_stamp(&track.path))))
+        .collect();
+    if pending.is_empty() {
+        return;
+    }
+
+    let mut changed_albums = HashSet::new();
+    for track in pending {
+        // Measured without holding the cache, a full decode takes a while
+        match measure(&track.path) {
+            Some((entry, tagged)) => {
+                if !tagged {
+                    if let Some(key) = album_key(track) {
+                        changed_albums.insert(key);
+                    } else if write_tags {
+                        let _ = write_replaygain_tags(&track.path, entry.track_gain(), entry.peak, None);
+                    }
+                }
+                let entry = restamp(&track.path, entry);
+                cache.lock().unwrap().entries.insert(track.path.clone(), entry);
+            }
+            None => {
+                failed.insert((track.path.clone(), file_stamp(&track.path)));
+            }
+        }
+    }
+
+    for key in changed_albums {
+        let members: Vec<&LibraryTrack> = tracks
+            .iter()
+            .filter(|track| album_key(track).as_ref() == Some(&key))
+            .collect();
+        let mut cache = cache.lock().unwrap();
+        // Album loudness is the energy average of the tracks, weighted by length
+        let measured: Vec<(f64, f64, f64)> = members
+            .iter()
+            .filter_map(|track| {
+                let entry = cache.get(&track.path)?;
+                Some((entry.loudness, entry.peak, track.duration.unwrap_or(1).max(1) as f64))
+            })
+            .collect();
+        let total: f64 = measured
+            .iter()
+            .map(|(_, _, length)| length)
+            .sum();
+        let energy: f64 = measured
+            .iter()
+            .map(|(loudness, _, length)| length * (10f64).powf(loudness / 10.0))
+            .sum();
+        if total <= 0.0 || energy <= 0.0 {
+            continue;
+        }
+        let album_loudness = 10.0 * (energy / total).log10();
+        let album_peak = measured
+            .iter()
+            .map(|(_, peak, _)| *peak)
+            .fold(0.0, f64::max);
+
+        for track in members {
+            let Some(entry) = cache.entries.get(&track.path).cloned() else {
+                continue;
+            };
+            let mut entry = GainEntry {
+                album_loudness: Some(album_loudness),
+                album_peak: Some(album_peak),
+                ..entry
+            };
+            if write_tags {
+                let album = Some((REFERENCE_LOUDNESS - album_loudness, album_peak));
+                if write_replaygain_tags(&track.path, entry.track_gain(), entry.peak, album).is_ok() {
+                    entry = restamp(&track.path, entry);
+                }
+            }
+            cache.entries.insert(track.path.clone(), entry);
+        }
+    }
+    let _ = cache.lock().unwrap().save();
+}
+
+// Takes the file's current stamp, writing tags changes it
+fn restamp(path: &Path, entry: GainEntry) -> GainEntry {
+    let (mtime, size) = file_stamp(path).unwrap_or((entry.mtime, entry.size));
+    GainEntry { mtime, size, ..entry }
+}
+
+// Gains already in the file's tags are taken as they are (the bool is true
+// then), anything else is measured
+fn measure(path: &Path) -> Option<(GainEntry, bool)> {
+    let (mtime, size) = file_stamp(path)?;
+    if let Some(entry) = read_tags(path, mtime, size) {
+        return Some((entry, true));
+    }
+
+    let output = Command::new(FFMPEG_PATH)
+        .args(["-hide_banner", "-nostats", "-i"])
+        .arg(path)
+        .args(["-map", "0:a:0", "-af", "ebur128=peak=true:framelog=verbose", "-f", "null", "-"])
+        .stdout(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // Only the summary at the end is logged at the default level
+    let log = String::from_utf8_lossy(&output.stderr);
+    let summary = &log[log.rfind("Summary:")?..];
+    let loudness = summary_value(summary, "I:")?;
+    let peak_db = summary_value(summary, "Peak:").unwrap_or(0.0);
+    let entry = GainEntry {
+        mtime,
+        size,
+        loudness,
+        peak: (10f64).powf(peak_db / 20.0),
+        album_loudness: None,
+        album_peak: None,
+    };
+    Some((entry, false))
+}
+
+fn summary_value(summary: &str, label: &str) -> Option<f64> {
+    let start = summary.find(label)? + label.len();
+    summary[start..].split_whitespace().next()?.parse().ok()
+}
+
+// "-3.21 dB" and "0.988312" style values, as every ReplayGain tagger writes them
+fn read_tags(path: &Path, mtime: u64, size: u64) -> Option<GainEntry> {
+    let output = Command::new(FFPROBE_PATH)
+        .args(["-v", "quiet", "-print_format", "json", "-show_format", "-show_streams"])
+        .arg(path)
+        .output()
+        .ok()?;
+    let json: Value = serde_json::from_slice(&output.stdout).ok()?;
+    // Vorbis comments sit on the stream in Ogg files, on the format elsewhere
+    let mut tag_sets = vec![&json["format"]["tags"]];
+    if let Some(streams) = json["streams"].as_array() {
+        tag_sets.extend(streams.iter().map(|stream| &stream["tags"]));
+    }
+    let tag = |name: &str| -> Option<f64> {
+        tag_sets
+            .iter()
+            .filter_map(|tags| tags.as_object())
+            .flat_map(|tags| tags.iter())
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| archive_field(value))
+            .and_then(|value| value.trim().trim_end_matches("dB").trim().parse().ok())
+    };
+    let gain = tag("replaygain_track_gain")?;
+    let peak = tag("replaygain_track_peak").unwrap_or(1.0);
+    let album_gain = tag("replaygain_album_gain");
+    Some(GainEntry {
+        mtime,
+        size,
+        loudness: REFERENCE_LOUDNESS - gain,
+        peak,
+        album_loudness: album_gain.map(|gain| REFERENCE_LOUDNESS - gain),
+        album_peak: album_gain.map(|_| tag("replaygain_album_peak").unwrap_or(peak)),
+    })
+}
@@ -1,30 +1,54 @@
 // search.rs
-use std::error::Error;
-use std::process::{ Command, exit };
+use std::process::Command;
 use serde_json::Value;
-use reqwest::Client;
-use crate::app::{ SearchResult, Source };
+use reqwest::blocking::Client;
+use crate::app::SearchResult;
+use crate::library::AUDIO_EXTENSIONS;
+use crate::sources::{ SourceResult, ARCHIVE, BANDCAMP, SOUNDCLOUD, YOUTUBE, YOUTUBE_MUSIC };
+use crate::xml;
 
 const YT_DLP_PATH: &str = "yt-dlp";
 
-pub async fn search_youtube(query: &str) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+pub fn search_youtube(query: &str) -> SourceResult<Vec<SearchResult>> {
+    search_with_yt_dlp(&format!("ytsearch15:{}", query), YOUTUBE)
+}
+
+pub fn search_soundcloud(query: &str) -> SourceResult<Vec<SearchResult>> {
+    search_with_yt_dlp(&format!("scsearch15:{}", query), SOUNDCLOUD)
+}
+
+/// Searches the songs section of music.youtube.com, which leaves out the
+/// covers, live recordings and reaction videos a plain YouTube search finds
+pub fn search_youtube_music(query: &str) -> SourceResult<Vec<SearchResult>> {
+    let mut url = reqwest::Url::parse_with_params("https://music.youtube.com/search", &[("q", query)])?;
+    url.set_fragment(Some("songs"));
+    search_with_yt_dlp(url.as_str(), YOUTUBE_MUSIC)
+}
+
+// Runs a yt-dlp search (a "ytsearch15:" style query or a search page URL)
+// and reads one flat entry per line
+fn search_with_yt_dlp(target: &str, source: &str) -> SourceResult<Vec<SearchResult>> {
     let output = Command::new(YT_DLP_PATH)
-        .arg("--default-search")
-        .arg("ytsearch")
-        .arg(format!("ytsearch15:{}", query))
+        .arg(target)
         .arg("--dump-json")
         .arg("--flat-playlist")
+        .arg("--playlist-end")
+        .arg("15")
         .arg("--skip-download")
         .arg("--ignore-errors")
         .output()?;
 
+    // Searches break for lack of a network, or an outdated yt-dlp, neither
+    // of which should end the session
     if !output.status.success() {
-        eprintln!(
-            "yt-dlp failed with status: {:?}, stderr: {}",
-            output.status,
-            String::from_utf8_lossy(&output.stderr)
-        );
-        exit(1);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or("no output")
+            .trim_start_matches("ERROR: ");
+        return Err(format!("yt-dlp failed ({}): {}", output.status, reason).into());
     }
 
     let stdout = String::from_utf8(output.stdout)?;
@@ -32,26 +56,221 @@ pub async fn search_youtube(query: &str) -> Result<Vec<SearchResult>, Box<dyn Er
         .lines()
         .filter_map(|line| {
             let json: Value = serde_json::from_str(line).ok()?;
+            yt_dlp_entry(&json, source, None)
+        })
+        .collect();
+
+    Ok(results)
+}
+
+// One entry of a flat yt-dlp listing. YouTube entries are kept by video id,
+// other sites by the URL of the track page.
+fn yt_dlp_entry(entry: &Value, source: &str, fallback_artist: Option<&str>) -> Option<SearchResult> {
+    let url = entry["webpage_url"].as_str().or_else(|| entry["url"].as_str());
+    let identifier = match source {
+        YOUTUBE | YOUTUBE_MUSIC => entry["id"].as_str()?.to_string(),
+        _ => url?.to_string(),
+    };
+    // Some extractors only list the URLs, the title is filled in on download
+    let title = entry["title"].as_str().or(url)?.to_string();
+    Some(SearchResult {
+        identifier,
+        title,
+        artist: entry["artist"]
+            .as_str()
+            .or_else(|| entry["channel"].as_str())
+            .or_else(|| entry["uploader"].as_str())
+            .or(fallback_artist)
+            .map(String::from),
+        year: None,
+        duration: entry["duration"].as_f64().map(|d| d as u64),
+        source: source.to_string(),
+    })
+}
+
+/// The video id of a youtube.com/watch, youtu.be, shorts or music.youtube.com link
+pub fn youtube_id(url: &str) -> Option<String> {
+    let url = url.trim();
+    if !url.contains("youtube.com/") && !url.contains("youtu.be/") {
+        return None;
+    }
+    let id = if let Some((_, query)) = url.split_once("v=") {
+        query.split(['&', '#']).next()
+    } else if let Some((_, rest)) = url.split_once("youtu.be/") {
+        rest.split(['?', '&', '#', '/']).next()
+    } else if let Some((_, rest)) = url.split_once("/shorts/") {
+        rest.split(['?', '&', '#', '/']).next()
+    } else {
+        None
+    }?;
+    if id.is_empty() { None } else { Some(id.to_string()) }
+}
+
+/// Links that point straight at an audio file, judged by the extension
+pub fn is_audio_url(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let file_name = path.rsplit('/').next().unwrap_or("");
+    match file_name.rsplit_once('.') {
+        Some((_, extension)) => AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// Playlist and channel links, as opposed to plain queries or single videos
+pub fn is_youtube_collection_url(query: &str) -> bool {
+    let query = query.trim();
+    let is_youtube = query.contains("youtube.com/") || query.contains("youtu.be/");
+    is_youtube &&
+        (query.contains("list=") ||
+            query.contains("/playlist") ||
+            query.contains("/@") ||
+            query.contains("/channel/") ||
+            query.contains("/c/") ||
+            query.contains("/user/"))
+}
+
+/// Lists the entries of a YouTube playlist or channel without resolving each
+/// video. Returns the playlist (or channel) title together with the entries.
+pub fn list_youtube_playlist(url: &str) -> SourceResult<(String, Vec<SearchResult>)> {
+    let mut url = url.trim().to_string();
+    // A bare channel link lists its tabs rather than its uploads
+    let is_channel_root =
+        !url.contains("list=") &&
+        !url.contains("/playlist") &&
+        !["/videos", "/streams", "/shorts", "/playlists"].iter().any(|tab| url.ends_with(tab));
+    if is_channel_root {
+        url = format!("{}/videos", url.trim_end_matches('/'));
+    }
+
+    list_with_yt_dlp(&url, YOUTUBE, "YouTube Playlist")
+}
+
+pub fn is_bandcamp_url(query: &str) -> bool {
+    let query = query.trim();
+    (query.starts_with("http://") || query.starts_with("https://")) && query.contains("bandcamp.com")
+}
+
+/// Lists the tracks of a Bandcamp album (or the releases of an artist page)
+pub fn list_bandcamp(url: &str) -> SourceResult<(String, Vec<SearchResult>)> {
+    list_with_yt_dlp(url.trim(), BANDCAMP, "Bandcamp Album")
+}
+
+fn list_with_yt_dlp(
+    url: &str,
+    source: &str,
+    default_title: &str
+) -> SourceResult<(String, Vec<SearchResult>)> {
+    let output = Command::new(YT_DLP_PATH)
+        .arg("--flat-playlist")
+        .arg("--dump-single-json")
+        .arg("--ignore-errors")
+        .arg(url)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(
+            format!(
+                "yt-dlp could not list the playlist: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ).into()
+        );
+    }
+
+    let json: Value = serde_json::from_slice(&output.stdout)?;
+    let title = json["title"].as_str().unwrap_or(default_title).to_string();
+    let channel = json["artist"]
+        .as_str()
+        .or_else(|| json["channel"].as_str())
+        .or_else(|| json["uploader"].as_str());
+    let results = json["entries"]
+        .as_array()
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(|entry| yt_dlp_entry(entry, source, channel))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok((title, results))
+}
+
+/// Bandcamp has no search yt-dlp knows about, so the track results of
+/// bandcamp.com/search are read from the page itself
+pub fn search_bandcamp(query: &str) -> SourceResult<Vec<SearchResult>> {
+    let client = Client::new();
+    let html = client
+        .get("https://bandcamp.com/search")
+        .query(&[("q", query), ("item_type", "t")])
+        .send()?
+        .text()?;
+
+    let results = html
+        .split("<li class=\"searchresult")
+        .skip(1)
+        .filter_map(|item| {
+            // The item URL is shown without the search tracking parameters
+            let url = html_text(item, "class=\"itemurl\"")?;
+            let title = html_text(item, "class=\"heading\"")?;
+            // "from Album by Artist", or only "by Artist" for singles
+            let subhead = html_text(item, "class=\"subhead\"").unwrap_or_default();
+            let artist = subhead
+                .rsplit_once("by ")
+                .map(|(_, artist)| artist.trim().to_string())
+                .filter(|artist| !artist.is_empty());
+            let year = html_text(item, "class=\"released\"").and_then(|released| {
+                let year = released.trim().rsplit(' ').next()?.to_string();
+                if year.len() == 4 { Some(year) } else { None }
+            });
             Some(SearchResult {
-                identifier: json.get("id")?.as_str()?.to_string(),
-                title: json.get("title")?.as_str()?.to_string(),
-                source: Source::YouTube,
+                identifier: url,
+                title,
+                artist,
+                year,
+                duration: None,
+                source: BANDCAMP.to_string(),
             })
         })
+        .take(15)
         .collect();
 
     Ok(results)
 }
 
-pub async fn search_archive(query: &str) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+// Text content of the first element carrying `marker` in its opening tag,
+// with inner tags dropped and whitespace collapsed
+fn html_text(html: &str, marker: &str) -> Option<String> {
+    let after_marker = &html[html.find(marker)?..];
+    let body = &after_marker[after_marker.find('>')? + 1..];
+    let body = &body[..body.find("</div>")?];
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in body.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+            }
+            '>' => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    let text = xml::unescape(&text.split_whitespace().collect::<Vec<_>>().join(" "));
+    if text.is_empty() { None } else { Some(text) }
+}
+
+pub fn search_archive(query: &str) -> SourceResult<Vec<SearchResult>> {
     let url = format!(
-        "https://archive.org/advancedsearch.php?q={}+mediatype:audio&output=json",
+        "https://archive.org/advancedsearch.php?q={}+mediatype:audio&fl[]=identifier&fl[]=title&fl[]=creator&fl[]=year&output=json",
         query.replace(" ", "+")
     );
 
     let client = Client::new();
-    let response = client.get(&url).send().await?;
-    let json: Value = response.json().await?;
+    let response = client.get(&url).send()?;
+    let json: Value = response.json()?;
 
     let mut results = Vec::new();
     if let Some(items) = json["response"]["docs"].as_array() {
@@ -65,7 +284,10 @@ pub async fn search_archive(query: &str) -> Result<Vec<SearchResult>, Box<dyn Er
                 results.push(SearchResult {
                     identifier: identifier.to_string(),
                     title: title.to_string(),
-                    source: Source::InternetArchive,
+                    artist: archive_field(&item["creator"]),
+                    year: archive_field(&item["year"]),
+                    duration: None,
+                    source: ARCHIVE.to_string(),
                 });
             }
         }
@@ -73,3 +295,13 @@ pub async fn search_archive(query: &str) -> Result<Vec<SearchResult>, Box<dyn Er
 
     Ok(results)
 }
+
+// Archive fields come back as a string, a number or a list of strings
+pub fn archive_field(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Array(values) => values.first().and_then(archive_field),
+        _ => None,
+    }
+}
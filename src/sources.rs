@@ -0,0 +1,669 @@
+// sources.rs
+// Everything pjplayer can search, play and download from goes through the
+// MusicSource trait. The registry holds the sources in the order they are
+// offered in the source selection.
+use std::collections::HashSet;
+use std::error::Error;
+use std::sync::{ Arc, Mutex };
+use crate::app::SearchResult;
+use crate::cache::AudioCache;
+use crate::config::Config;
+use crate::download::{ fetch_archive, fetch_direct, fetch_with_yt_dlp, DownloadOptions, Downloaded };
+use crate::history::DownloadHistory;
+use crate::library::{ Library, LibraryTrack };
+use crate::podcasts::{ PodcastSource, PodcastStore };
+use crate::radio::{ RadioFavorites, RadioSource };
+use crate::search::{
+    is_audio_url,
+    is_bandcamp_url,
+    is_youtube_collection_url,
+    list_bandcamp,
+    list_youtube_playlist,
+    search_archive,
+    search_bandcamp,
+    search_soundcloud,
+    search_youtube,
+    search_youtube_music,
+    youtube_id,
+};
+
+// Keys of the built-in sources, stored with every track in the history,
+// playlists and library
+pub const YOUTUBE: &str = "youtube";
+pub const YOUTUBE_MUSIC: &str = "youtube_music";
+pub const SOUNDCLOUD: &str = "soundcloud";
+pub const BANDCAMP: &str = "bandcamp";
+pub const ARCHIVE: &str = "archive";
+pub const RADIO: &str = "radio";
+pub const PODCAST: &str = "podcast";
+pub const LOCAL: &str = "local";
+pub const SAVED: &str = "saved";
+pub const URL: &str = "url";
+
+// Sources run on search and download threads, so their errors have to cross threads too
+pub type SourceResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// How ffmpeg gets at the audio of a track
+pub enum Stream {
+    // A file or audio URL ffmpeg opens itself
+    Direct(String),
+    // A page yt-dlp extracts the audio from first
+    Extract(String),
+    // An Icecast or Shoutcast stream, relayed by pjplayer to read the song titles
+    Live(String),
+}
+
+impl Stream {
+    /// Whether playing it needs the network
+    pub fn is_remote(&self) -> bool {
+        match self {
+            Stream::Direct(location) => location.starts_with("http://") || location.starts_with("https://"),
+            _ => true,
+        }
+    }
+}
+
+pub trait MusicSource: Send + Sync {
+    /// Stable name stored with the tracks of this source
+    fn key(&self) -> &str;
+
+    fn label(&self) -> &str;
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>>;
+
+    /// Whether `query` is a playlist, album or channel link `list_tracks` understands
+    fn is_collection(&self, _query: &str) -> bool {
+        false
+    }
+
+    /// Title and entries of a collection link
+    fn list_tracks(&self, _url: &str) -> SourceResult<(String, Vec<SearchResult>)> {
+        Err(format!("{} has no playlists", self.label()).into())
+    }
+
+    /// Recognizes a link pasted into the search box as one of this source's tracks
+    fn track_from_url(&self, _url: &str) -> Option<SearchResult> {
+        None
+    }
+
+    /// Page, file URL or path of the track, as written to exported playlists
+    fn location(&self, track: &SearchResult) -> String;
+
+    fn resolve(&self, track: &SearchResult) -> SourceResult<Stream>;
+
+    /// Whether searching works without a network
+    fn works_offline(&self) -> bool {
+        false
+    }
+
+    /// Whether the source is offered when choosing where to download from
+    fn can_download(&self) -> bool {
+        true
+    }
+
+    /// Whether downloading the track asks for format and quality first
+    fn has_download_options(&self, _track: &SearchResult) -> bool {
+        false
+    }
+
+    /// Downloads one track and records it in the history. Blocking, only
+    /// called from download threads. `playlist` carries the playlist title and
+    /// position, used as album and track number when the track has none.
+    fn download(
+        &self,
+        track: &SearchResult,
+        options: DownloadOptions,
+        config: &Config,
+        history: &Mutex<DownloadHistory>,
+        playlist: Option<(&str, u32)>
+    ) -> SourceResult<Downloaded>;
+}
+
+#[derive(Clone)]
+pub struct SourceRegistry {
+    sources: Vec<Arc<dyn MusicSource>>,
+    // Pasted links no other source claims. Not offered for searching.
+    links: Arc<dyn MusicSource>,
+}
+
+impl SourceRegistry {
+    pub fn new(
+        config: &Config,
+        library: Arc<Mutex<Library>>,
+        radio_favorites: Arc<Mutex<RadioFavorites>>,
+        podcasts: Arc<Mutex<PodcastStore>>,
+        history: Arc<Mutex<DownloadHistory>>,
+        cache: AudioCache
+    ) -> SourceRegistry {
+        SourceRegistry {
+            sources: vec![
+                Arc::new(YouTubeSource),
+                Arc::new(YouTubeMusicSource),
+                Arc::new(SoundCloudSource),
+                Arc::new(BandcampSource),
+                Arc::new(ArchiveSource),
+                Arc::new(RadioSource::new(config, radio_favorites)),
+                Arc::new(PodcastSource::new(podcasts)),
+                Arc::new(LocalSource { library: Arc::clone(&library) }),
+                Arc::new(SavedSource { history, library, cache })
+            ],
+            links: Arc::new(LinkSource),
+        }
+    }
+
+    /// Adds a source after the built-in ones. A source whose key is taken is ignored.
+    pub fn register(&mut self, source: Arc<dyn MusicSource>) {
+        if self.get(source.key()).is_none() {
+            self.sources.push(source);
+        }
+    }
+
+    pub fn all(&self) -> &[Arc<dyn MusicSource>] {
+        &self.sources
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<dyn MusicSource>> {
+        self.sources
+            .iter()
+            .chain(std::iter::once(&self.links))
+            .find(|source| source.key() == key)
+            .cloned()
+    }
+
+    /// Label of the source with `key`, or the key itself for sources that are gone
+    pub fn label<'a>(&'a self, key: &'a str) -> &'a str {
+        self.sources
+            .iter()
+            .chain(std::iter::once(&self.links))
+            .find(|source| source.key() == key)
+            .map(|source| source.label())
+            .unwrap_or(key)
+    }
+
+    pub fn location(&self, track: &SearchResult) -> String {
+        match self.get(&track.source) {
+            Some(source) => source.location(track),
+            None => track.identifier.clone(),
+        }
+    }
+
+    /// The source that lists `query` as a playlist, album or channel
+    pub fn collection_source(&self, query: &str) -> Option<Arc<dyn MusicSource>> {
+        self.sources
+            .iter()
+            .find(|source| source.is_collection(query))
+            .cloned()
+    }
+
+    /// Turns a link into a track of the first source that recognizes it,
+    /// falling back to a plain web link. None when `url` is not a link.
+    pub fn track_from_url(&self, url: &str) -> Option<SearchResult> {
+        self.sources
+            .iter()
+            .find_map(|source| source.track_from_url(url))
+            .or_else(|| self.links.track_from_url(url))
+    }
+}
+
+fn is_link(url: &str) -> bool {
+    (url.starts_with("http://") || url.starts_with("https://")) && !url.contains(char::is_whitespace)
+}
+
+// The real title is only known once yt-dlp or the file itself is read, until
+// then links to audio files go by the file name and pages by the URL
+fn link_track(url: &str, source: &str, identifier: String) -> SearchResult {
+    let title = if is_audio_url(url) {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        path.rsplit('/').next().unwrap_or(url).to_string()
+    } else {
+        url.to_string()
+    };
+    SearchResult {
+        identifier,
+        title,
+        artist: None,
+        year: None,
+        duration: None,
+        source: source.to_string(),
+    }
+}
+
+struct YouTubeSource;
+
+impl MusicSource for YouTubeSource {
+    fn key(&self) -> &str {
+        YOUTUBE
+    }
+
+    fn label(&self) -> &str {
+        "YouTube"
+    }
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        search_youtube(query)
+    }
+
+    fn is_collection(&self, query: &str) -> bool {
+        is_youtube_collection_url(query)
+    }
+
+    fn list_tracks(&self, url: &str) -> SourceResult<(String, Vec<SearchResult>)> {
+        list_youtube_playlist(url)
+    }
+
+    // Also claims music.youtube.com links, the video ids are the same
+    fn track_from_url(&self, url: &str) -> Option<SearchResult> {
+        let url = url.trim();
+        let id = youtube_id(url).filter(|_| is_link(url))?;
+        Some(link_track(url, YOUTUBE, id))
+    }
+
+    fn location(&self, track: &SearchResult) -> String {
+        format!("https://www.youtube.com/watch?v={}", track.identifier)
+    }
+
+    fn resolve(&self, track: &SearchResult) -> SourceResult<Stream> {
+        Ok(Stream::Extract(self.location(track)))
+    }
+
+    fn has_download_options(&self, _track: &SearchResult) -> bool {
+        true
+    }
+
+    fn download(
+        &self,
+        track: &SearchResult,
+        options: DownloadOptions,
+        config: &Config,
+        history: &Mutex<DownloadHistory>,
+        playlist: Option<(&str, u32)>
+    ) -> SourceResult<Downloaded> {
+        fetch_with_yt_dlp(track, &self.location(track), options, config, history, playlist)
+    }
+}
+
+struct YouTubeMusicSource;
+
+impl MusicSource for YouTubeMusicSource {
+    fn key(&self) -> &str {
+        YOUTUBE_MUSIC
+    }
+
+    fn label(&self) -> &str {
+        "YouTube Music"
+    }
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        search_youtube_music(query)
+    }
+
+    fn location(&self, track: &SearchResult) -> String {
+        format!("https://music.youtube.com/watch?v={}", track.identifier)
+    }
+
+    fn resolve(&self, track: &SearchResult) -> SourceResult<Stream> {
+        Ok(Stream::Extract(self.location(track)))
+    }
+
+    fn has_download_options(&self, _track: &SearchResult) -> bool {
+        true
+    }
+
+    fn download(
+        &self,
+        track: &SearchResult,
+        options: DownloadOptions,
+        config: &Config,
+        history: &Mutex<DownloadHistory>,
+        playlist: Option<(&str, u32)>
+    ) -> SourceResult<Downloaded> {
+        fetch_with_yt_dlp(track, &self.location(track), options, config, history, playlist)
+    }
+}
+
+// Tracks are kept by the URL of their page, which is also what yt-dlp's
+// scsearch gives back
+struct SoundCloudSource;
+
+impl MusicSource for SoundCloudSource {
+    fn key(&self) -> &str {
+        SOUNDCLOUD
+    }
+
+    fn label(&self) -> &str {
+        "SoundCloud"
+    }
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        search_soundcloud(query)
+    }
+
+    fn track_from_url(&self, url: &str) -> Option<SearchResult> {
+        let url = url.trim();
+        if !is_link(url) || !url.contains("soundcloud.com/") || is_audio_url(url) {
+            return None;
+        }
+        Some(link_track(url, SOUNDCLOUD, url.to_string()))
+    }
+
+    fn location(&self, track: &SearchResult) -> String {
+        track.identifier.clone()
+    }
+
+    fn resolve(&self, track: &SearchResult) -> SourceResult<Stream> {
+        Ok(Stream::Extract(self.location(track)))
+    }
+
+    fn has_download_options(&self, _track: &SearchResult) -> bool {
+        true
+    }
+
+    fn download(
+        &self,
+        track: &SearchResult,
+        options: DownloadOptions,
+        config: &Config,
+        history: &Mutex<DownloadHistory>,
+        playlist: Option<(&str, u32)>
+    ) -> SourceResult<Downloaded> {
+        fetch_with_yt_dlp(track, &self.location(track), options, config, history, playlist)
+    }
+}
+
+// Track pages from the search and album pages are what gets stored, the
+// audio URLs on them expire
+struct BandcampSource;
+
+impl MusicSource for BandcampSource {
+    fn key(&self) -> &str {
+        BANDCAMP
+    }
+
+    fn label(&self) -> &str {
+        "Bandcamp"
+    }
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        search_bandcamp(query)
+    }
+
+    // Album and artist pages, single track pages are played as they are
+    fn is_collection(&self, query: &str) -> bool {
+        is_bandcamp_url(query) && !query.contains("/track/")
+    }
+
+    fn list_tracks(&self, url: &str) -> SourceResult<(String, Vec<SearchResult>)> {
+        list_bandcamp(url)
+    }
+
+    fn track_from_url(&self, url: &str) -> Option<SearchResult> {
+        let url = url.trim();
+        if !is_link(url) || !is_bandcamp_url(url) || is_audio_url(url) {
+            return None;
+        }
+        Some(link_track(url, BANDCAMP, url.to_string()))
+    }
+
+    fn location(&self, track: &SearchResult) -> String {
+        track.identifier.clone()
+    }
+
+    fn resolve(&self, track: &SearchResult) -> SourceResult<Stream> {
+        Ok(Stream::Extract(self.location(track)))
+    }
+
+    fn has_download_options(&self, _track: &SearchResult) -> bool {
+        true
+    }
+
+    fn download(
+        &self,
+        track: &SearchResult,
+        options: DownloadOptions,
+        config: &Config,
+        history: &Mutex<DownloadHistory>,
+        playlist: Option<(&str, u32)>
+    ) -> SourceResult<Downloaded> {
+        fetch_with_yt_dlp(track, &self.location(track), options, config, history, playlist)
+    }
+}
+
+// Identifiers are item names, or "item/file" for a single file of an item
+struct ArchiveSource;
+
+impl MusicSource for ArchiveSource {
+    fn key(&self) -> &str {
+        ARCHIVE
+    }
+
+    fn label(&self) -> &str {
+        "Internet Archive"
+    }
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        search_archive(query)
+    }
+
+    fn track_from_url(&self, url: &str) -> Option<SearchResult> {
+        let url = url.trim();
+        if !is_link(url) {
+            return None;
+        }
+        let (_, rest) = url
+            .split_once("archive.org/details/")
+            .or_else(|| url.split_once("archive.org/download/"))?;
+        let identifier = rest.split(['?', '#']).next()?.trim_end_matches('/').to_string();
+        Some(link_track(url, ARCHIVE, identifier))
+    }
+
+    fn location(&self, track: &SearchResult) -> String {
+        if track.identifier.contains('/') {
+            format!("https://archive.org/download/{}", track.identifier)
+        } else {
+            format!("https://archive.org/details/{}", track.identifier)
+        }
+    }
+
+    // Items with several files are playlists to yt-dlp, the first one is played
+    fn resolve(&self, track: &SearchResult) -> SourceResult<Stream> {
+        if track.identifier.contains('/') {
+            Ok(Stream::Direct(self.location(track)))
+        } else {
+            Ok(Stream::Extract(self.location(track)))
+        }
+    }
+
+    fn download(
+        &self,
+        track: &SearchResult,
+        _options: DownloadOptions,
+        config: &Config,
+        history: &Mutex<DownloadHistory>,
+        _playlist: Option<(&str, u32)>
+    ) -> SourceResult<Downloaded> {
+        if track.identifier.contains('/') {
+            fetch_direct(track, &self.location(track), config, history)
+        } else {
+            fetch_archive(track, config, history)
+        }
+    }
+}
+
+// Tracks are kept by their path
+struct LocalSource {
+    library: Arc<Mutex<Library>>,
+}
+
+impl MusicSource for LocalSource {
+    fn key(&self) -> &str {
+        LOCAL
+    }
+
+    fn label(&self) -> &str {
+        "Local Library"
+    }
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        Ok(self.library.lock().unwrap().search(query))
+    }
+
+    fn location(&self, track: &SearchResult) -> String {
+        track.identifier.clone()
+    }
+
+    fn resolve(&self, track: &SearchResult) -> SourceResult<Stream> {
+        Ok(Stream::Direct(track.identifier.clone()))
+    }
+
+    fn works_offline(&self) -> bool {
+        true
+    }
+
+    // The files are already on disk
+    fn can_download(&self) -> bool {
+        false
+    }
+
+    fn download(
+        &self,
+        _track: &SearchResult,
+        _options: DownloadOptions,
+        _config: &Config,
+        _history: &Mutex<DownloadHistory>,
+        _playlist: Option<(&str, u32)>
+    ) -> SourceResult<Downloaded> {
+        Err("Library tracks are already downloaded".into())
+    }
+}
+
+// Everything playable without a network besides the library: downloaded
+// files (wherever they were saved) and tracks in the audio cache. Results
+// keep the source they play from, a local file or the cached track.
+struct SavedSource {
+    history: Arc<Mutex<DownloadHistory>>,
+    library: Arc<Mutex<Library>>,
+    cache: AudioCache,
+}
+
+impl MusicSource for SavedSource {
+    fn key(&self) -> &str {
+        SAVED
+    }
+
+    fn label(&self) -> &str {
+        "Downloads and Cache"
+    }
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        let paths = self.history.lock().unwrap().paths();
+        let library = self.library.lock().unwrap();
+        let downloads = paths.into_iter().map(|path| {
+            // Files outside the library go by their name
+            library.tracks
+                .iter()
+                .find(|track| track.path == path)
+                .cloned()
+                .unwrap_or_else(|| LibraryTrack {
+                    title: path
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    path: path.clone(),
+                    ..LibraryTrack::default()
+                })
+                .to_search_result()
+        });
+        let mut seen = HashSet::new();
+        Ok(
+            downloads
+                .chain(self.cache.search(query))
+                .filter(|track| track.matches(query))
+                .filter(|track| seen.insert((track.source.clone(), track.identifier.clone())))
+                .collect()
+        )
+    }
+
+    fn location(&self, track: &SearchResult) -> String {
+        track.identifier.clone()
+    }
+
+    // Results belong to other sources, this one never plays anything itself
+    fn resolve(&self, track: &SearchResult) -> SourceResult<Stream> {
+        Ok(Stream::Direct(track.identifier.clone()))
+    }
+
+    fn works_offline(&self) -> bool {
+        true
+    }
+
+    fn can_download(&self) -> bool {
+        false
+    }
+
+    fn download(
+        &self,
+        _track: &SearchResult,
+        _options: DownloadOptions,
+        _config: &Config,
+        _history: &Mutex<DownloadHistory>,
+        _playlist: Option<(&str, u32)>
+    ) -> SourceResult<Downloaded> {
+        Err("Saved tracks are already on disk".into())
+    }
+}
+
+// Any other link: a page yt-dlp understands or a plain audio file, kept by URL
+struct LinkSource;
+
+impl MusicSource for LinkSource {
+    fn key(&self) -> &str {
+        URL
+    }
+
+    fn label(&self) -> &str {
+        "Web Link"
+    }
+
+    fn search(&self, query: &str) -> SourceResult<Vec<SearchResult>> {
+        Ok(self.track_from_url(query).into_iter().collect())
+    }
+
+    fn track_from_url(&self, url: &str) -> Option<SearchResult> {
+        let url = url.trim();
+        if !is_link(url) {
+            return None;
+        }
+        Some(link_track(url, URL, url.to_string()))
+    }
+
+    fn location(&self, track: &SearchResult) -> String {
+        track.identifier.clone()
+    }
+
+    fn resolve(&self, track: &SearchResult) -> SourceResult<Stream> {
+        if is_audio_url(&track.identifier) {
+            Ok(Stream::Direct(track.identifier.clone()))
+        } else {
+            Ok(Stream::Extract(track.identifier.clone()))
+        }
+    }
+
+    fn has_download_options(&self, track: &SearchResult) -> bool {
+        !is_audio_url(&track.identifier)
+    }
+
+    fn download(
+        &self,
+        track: &SearchResult,
+        options: DownloadOptions,
+        config: &Config,
+        history: &Mutex<DownloadHistory>,
+        playlist: Option<(&str, u32)>
+    ) -> SourceResult<Downloaded> {
+        if is_audio_url(&track.identifier) {
+            fetch_direct(track, &track.identifier, config, history)
+        } else {
+            fetch_with_yt_dlp(track, &track.identifier, options, config, history, playlist)
+        }
+    }
+}
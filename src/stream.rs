@@ -1,39 +1,399 @@
 use std::error::Error;
-use std::process::{ Command, Stdio, Child };
+use std::process::{ Command, Stdio, Child, ChildStdin, ChildStdout };
+use std::sync::atomic::{ AtomicUsize, Ordering };
 use std::sync::{ Arc, Mutex };
 use std::fs::File;
-use std::io::Read;
+use std::io::{ BufRead, BufReader, Read, Write };
 use std::thread;
 use std::time::Duration;
+use crate::cache::{ tee_into, PendingEntry };
+use crate::radio::relay_stream;
+use crate::recorder::StreamRecorder;
+use crate::sources::Stream;
 
 const YT_DLP_PATH: &str = "yt-dlp";
-const FFMPEG_PATH: &str = "ffplay";
-
-pub fn stream_audio(
-    video_id: &str,
-    visualization_data: Arc<Mutex<Vec<u8>>>
-) -> Result<Child, Box<dyn Error>> {
-    let youtube_url = format!("https://www.youtube.com/watch?v={}", video_id);
-    let output = Command::new(YT_DLP_PATH).args(&["-s", "--get-title", &youtube_url]).output()?;
-    let song_name = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    println!("Streaming: {}", song_name);
-
-    let yt_dlp = Command::new(YT_DLP_PATH)
-        .args(&["-o", "-", "-f", "bestaudio", "--quiet", &youtube_url])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .spawn()?;
+const FFMPEG_PATH: &str = "ffmpeg";
+const FFPLAY_PATH: &str = "ffplay";
+
+// Every track is decoded to 16-bit stereo at this rate, so one ffplay can
+// play them all one after another
+const SAMPLE_RATE: u32 = 44100;
+const FRAME_BYTES: usize = 4;
+/// Longest crossfade the config accepts, in seconds
+pub const MAX_CROSSFADE: f64 = 12.0;
+
+// Speeds the [ and ] keys step through
+const SPEEDS: [f64; 9] = [0.5, 0.75, 1.0, 1.25, 1.5, 1.75, 2.0, 2.5, 3.0];
+
+/// The step after (or before) `speed`, staying within the supported range
+pub fn step_speed(speed: f64, faster: bool) -> f64 {
+    let next = if faster {
+        SPEEDS.iter().find(|&&s| s > speed + 0.01)
+    } else {
+        SPEEDS.iter().rev().find(|&&s| s < speed - 0.01)
+    };
+    next.copied().unwrap_or(speed)
+}
+
+pub fn clamp_speed(speed: f64) -> f64 {
+    speed.clamp(SPEEDS[0], SPEEDS[SPEEDS.len() - 1])
+}
+
+// atempo stretches time without changing the pitch. Older ffmpeg builds take
+// at most 2.0 per filter, so faster speeds are chained.
+fn atempo_filter(mut speed: f64) -> String {
+    let mut filters = Vec::new();
+    while speed > 2.0 {
+        filters.push("atempo=2.0".to_string());
+        speed /= 2.0;
+    }
+    filters.push(format!("atempo={}", speed));
+    filters.join(",")
+}
+
+// Smart speed cuts silences longer than half a second down to this much
+const KEPT_SILENCE: f64 = 0.25;
+// Loudness normalization aims for this integrated loudness, in LUFS
+const TARGET_LOUDNESS: f64 = -16.0;
+
+/// How a track is played: its speed and the filters for spoken word
+#[derive(Debug, Clone, Copy)]
+pub struct PlaybackEffects {
+    pub speed: f64,
+    // ReplayGain adjustment in dB
+    pub gain: Option<f64>,
+    // Shortens silences
+    pub smart_speed: bool,
+    // EBU R128 loudness normalization
+    pub normalize: bool,
+}
+
+impl Default for PlaybackEffects {
+    fn default() -> Self {
+        PlaybackEffects { speed: 1.0, gain: None, smart_speed: false, normalize: false }
+    }
+}
+
+impl PlaybackEffects {
+    // The ffmpeg audio filter chain, None when the audio plays untouched.
+    // silencedetect and ebur128 only measure, their log lines feed the
+    // `PlaybackReport`.
+    fn filter_chain(&self) -> Option<String> {
+        let mut filters = Vec::new();
+        if let Some(gain) = self.gain.filter(|gain| gain.abs() >= 0.01) {
+            filters.push(format!("volume={:.2}dB", gain));
+        }
+        if self.smart_speed {
+            filters.push("silencedetect=noise=-40dB:duration=0.5".to_string());
+            filters.push(
+                format!(
+                    "silenceremove=stop_periods=-1:stop_duration=0.5:stop_threshold=-40dB:stop_silence={}",
+                    KEPT_SILENCE
+                )
+            );
+        }
+        if self.normalize {
+            // loudnorm works at 192 kHz, the decoder's output rate brings it back down
+            filters.push("ebur128=framelog=info".to_string());
+            filters.push(format!("loudnorm=I={}:TP=-1.5:LRA=11", TARGET_LOUDNESS));
+        }
+        if (self.speed - 1.0).abs() > 0.01 {
+            filters.push(atempo_filter(self.speed));
+        }
+        if filters.is_empty() { None } else { Some(filters.join(",")) }
+    }
 
-    let ffplay_stdin = yt_dlp.stdout.unwrap();
-    let visualization_data_clone = Arc::clone(&visualization_data);
-    let ffplay = Command::new(FFMPEG_PATH)
-        .args(&["-nodisp", "-autoexit", "-loglevel", "quiet", "-"])
-        .stdin(ffplay_stdin)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
+    fn reports(&self) -> bool {
+        self.smart_speed || self.normalize
+    }
+}
+
+/// What the spoken-word filters have done so far in the playing track
+#[derive(Debug, Default)]
+pub struct PlaybackReport {
+    // Seconds of silence smart speed has cut
+    pub silence_saved: f64,
+    // Integrated loudness of the track so far, before normalization
+    pub loudness: Option<f64>,
+}
+
+impl PlaybackReport {
+    /// Gain normalization applies to reach the target loudness, in dB
+    pub fn gain(&self) -> Option<f64> {
+        self.loudness.map(|loudness| TARGET_LOUDNESS - loudness)
+    }
+
+    fn read_log_line(&mut self, line: &str) {
+        if let Some(duration) = log_value(line, "silence_duration:") {
+            self.silence_saved += (duration - KEPT_SILENCE).max(0.0);
+        } else if line.contains("ebur128") {
+            // Below -70 LUFS ebur128 has not measured anything yet
+            if let Some(loudness) = log_value(line, " I:").filter(|loudness| *loudness > -70.0) {
+                self.loudness = Some(loudness);
+            }
+        }
+    }
+}
+
+// The number following `label` in an ffmpeg filter log line
+fn log_value(line: &str, label: &str) -> Option<f64> {
+    let start = line.find(label)? + label.len();
+    line[start..].split_whitespace().next()?.parse().ok()
+}
+
+/// A track being decoded to raw audio, waiting for or handed to a `Playback`
+pub struct Decoder {
+    output: ChildStdout,
+    // ffmpeg, and yt-dlp feeding it for extracted pages
+    processes: Vec<Child>,
+}
+
+/// Starts decoding `stream`, `start` seconds in and with `effects`. Live
+/// streams always play from now, at normal speed and without smart speed.
+/// Audio yt-dlp fetches is also written to `keep`, when given, and live
+/// streams go through `recorder`.
+pub fn start_decoder(
+    stream: &Stream,
+    start: u64,
+    effects: PlaybackEffects,
+    stream_title: Arc<Mutex<Option<String>>>,
+    recorder: Arc<Mutex<StreamRecorder>>,
+    report: Arc<Mutex<PlaybackReport>>,
+    keep: Option<PendingEntry>
+) -> Result<Decoder, Box<dyn Error>> {
+    let mut processes = Vec::new();
+    let mut tee = None;
+    let (input, stdin) = match stream {
+        // Files and plain audio links need no extractor, ffmpeg reads them directly
+        Stream::Direct(location) => (location.as_str(), Stdio::null()),
+        Stream::Extract(page) => {
+            // Pages that are playlists to yt-dlp play their first entry
+            let mut yt_dlp = Command::new(YT_DLP_PATH)
+                .args(["-o", "-", "-f", "bestaudio", "--quiet", "--playlist-items", "1"])
+                .arg(page)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()?;
+            // A pipe cannot seek, ffmpeg decodes its way to `start` instead
+            match keep {
+                Some(pending) => {
+                    tee = Some((yt_dlp, pending));
+                    ("-", Stdio::piped())
+                }
+                None => {
+                    let piped = Stdio::from(yt_dlp.stdout.take().unwrap());
+                    processes.push(yt_dlp);
+                    ("-", piped)
+                }
+            }
+        }
+        Stream::Live(_) => ("-", Stdio::piped()),
+    };
+
+    let mut ffmpeg_command = Command::new(FFMPEG_PATH);
+    ffmpeg_command
+        .args(["-nostdin", "-ss", &start.to_string(), "-i", input, "-vn"])
+        .stdin(stdin)
+        .stdout(Stdio::piped());
+    if let Some(filters) = effects.filter_chain() {
+        ffmpeg_command.args(["-af", &filters]);
+    }
+    // The measuring filters log at info level
+    if effects.reports() {
+        ffmpeg_command.args(["-loglevel", "info", "-nostats"]).stderr(Stdio::piped());
+    } else {
+        ffmpeg_command.args(["-loglevel", "quiet"]).stderr(Stdio::null());
+    }
+    let mut ffmpeg = ffmpeg_command
+        .args(["-f", "s16le", "-ar", &SAMPLE_RATE.to_string(), "-ac", "2", "-"])
         .spawn()?;
 
-    let ffplay_id = ffplay.id();
+    if let Some((mut yt_dlp, pending)) = tee {
+        let ffmpeg_stdin = ffmpeg.stdin.take().unwrap();
+        let yt_dlp_stdout = yt_dlp.stdout.take().unwrap();
+        thread::spawn(move || {
+            tee_into(yt_dlp_stdout, ffmpeg_stdin, pending, || {
+                yt_dlp.wait().is_ok_and(|status| status.success())
+            });
+            // Still running when playback stopped before the end
+            let _ = yt_dlp.kill();
+            let _ = yt_dlp.wait();
+        });
+    }
+
+    if let Stream::Live(url) = stream {
+        // Ends by itself once ffmpeg is gone and the pipe breaks
+        let ffmpeg_stdin = ffmpeg.stdin.take().unwrap();
+        let url = url.clone();
+        thread::spawn(move || {
+            let _ = relay_stream(&url, ffmpeg_stdin, stream_title, recorder);
+        });
+    }
+
+    if let Some(stderr) = ffmpeg.stderr.take() {
+        thread::spawn(move || {
+            // Keeps draining until ffmpeg exits, a full pipe would stall playback
+            let mut reader = BufReader::new(stderr);
+            let mut line = Vec::new();
+            while matches!(reader.read_until(b'\n', &mut line), Ok(n) if n > 0) {
+                report.lock().unwrap().read_log_line(&String::from_utf8_lossy(&line));
+                line.clear();
+            }
+        });
+    }
+
+    let output = ffmpeg.stdout.take().unwrap();
+    processes.push(ffmpeg);
+    Ok(Decoder { output, processes })
+}
+
+/// One ffplay playing decoded tracks back to back. The decoder of the next
+/// track is queued while the current one plays, so it is already running
+/// when the switch comes and nothing is heard in between, or the two
+/// overlap by the crossfade.
+pub struct Playback {
+    ffplay: Child,
+    next: Arc<Mutex<Option<ChildStdout>>>,
+    advanced: Arc<AtomicUsize>,
+    processes: Vec<Child>,
+}
+
+impl Playback {
+    pub fn start(
+        decoder: Decoder,
+        crossfade: f64,
+        visualization_data: Arc<Mutex<Vec<u8>>>
+    ) -> Result<Playback, Box<dyn Error>> {
+        let mut ffplay = Command::new(FFPLAY_PATH)
+            .args(["-nodisp", "-autoexit", "-loglevel", "quiet", "-f", "s16le"])
+            .args(["-sample_rate", &SAMPLE_RATE.to_string(), "-ch_layout", "stereo", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let sink = ffplay.stdin.take().unwrap();
+        let next = Arc::new(Mutex::new(None));
+        let advanced = Arc::new(AtomicUsize::new(0));
+        let overlap = ((crossfade * (SAMPLE_RATE as f64)) as usize) * FRAME_BYTES;
+
+        let next_clone = Arc::clone(&next);
+        let advanced_clone = Arc::clone(&advanced);
+        thread::spawn(move || {
+            relay_tracks(decoder.output, sink, next_clone, advanced_clone, overlap);
+        });
+        spawn_visualization(ffplay.id(), visualization_data);
+
+        Ok(Playback { ffplay, next, advanced, processes: decoder.processes })
+    }
+
+    /// Queues the track that plays once the current one ends
+    pub fn queue_next(&mut self, decoder: Decoder) {
+        *self.next.lock().unwrap() = Some(decoder.output);
+        self.processes.extend(decoder.processes);
+    }
+
+    /// How many times playback has moved on to a queued track
+    pub fn advanced(&self) -> usize {
+        self.advanced.load(Ordering::SeqCst)
+    }
+
+    /// True once the last track has ended and ffplay has exited
+    pub fn is_finished(&mut self) -> bool {
+        // Decoders of tracks played through are done by now
+        self.processes.retain_mut(|process| !matches!(process.try_wait(), Ok(Some(_))));
+        matches!(self.ffplay.try_wait(), Ok(Some(_)))
+    }
+
+    /// The ffplay process, paused and resumed with signals
+    pub fn id(&self) -> u32 {
+        self.ffplay.id()
+    }
+
+    pub fn stop(mut self) {
+        for process in self.processes.iter_mut().chain([&mut self.ffplay]) {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+    }
+}
+
+// Copies decoded audio into ffplay and moves on to the queued decoder when a
+// track ends. The last `overlap` bytes of each track are held back to be
+// mixed with the start of the next one.
+fn relay_tracks(
+    mut current: ChildStdout,
+    mut sink: ChildStdin,
+    next: Arc<Mutex<Option<ChildStdout>>>,
+    advanced: Arc<AtomicUsize>,
+    overlap: usize
+) {
+    let mut held = Vec::new();
+    let mut buffer = [0u8; 16384];
+    loop {
+        match current.read(&mut buffer) {
+            Ok(n) if n > 0 => {
+                held.extend_from_slice(&buffer[..n]);
+                if held.len() > overlap {
+                    let ready = ((held.len() - overlap) / FRAME_BYTES) * FRAME_BYTES;
+                    if sink.write_all(&held[..ready]).is_err() {
+                        return;
+                    }
+                    held.drain(..ready);
+                }
+            }
+            _ => {
+                let upcoming = next.lock().unwrap().take();
+                let Some(mut upcoming) = upcoming else {
+                    // Nothing queued: ffplay plays what it has and exits
+                    let _ = sink.write_all(&held);
+                    return;
+                };
+                advanced.fetch_add(1, Ordering::SeqCst);
+                let mut head = vec![0u8; (held.len() / FRAME_BYTES) * FRAME_BYTES];
+                let read = read_up_to(&mut upcoming, &mut head);
+                crossfade(&mut held, &head[..read]);
+                if sink.write_all(&held).is_err() {
+                    return;
+                }
+                held.clear();
+                current = upcoming;
+            }
+        }
+    }
+}
+
+// Fills as much of `buffer` as the reader has before it ends
+fn read_up_to(reader: &mut impl Read, buffer: &mut [u8]) -> usize {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(n) if n > 0 => {
+                filled += n;
+            }
+            _ => {
+                break;
+            }
+        }
+    }
+    filled
+}
+
+// Fades `tail` out while `head` fades in, linearly over the length of `tail`
+fn crossfade(tail: &mut [u8], head: &[u8]) {
+    let frames = tail.len() / FRAME_BYTES;
+    for (i, sample) in tail.chunks_exact_mut(2).enumerate() {
+        // Both channels of a frame fade alike
+        let fade_in = ((i / 2) as f64) / (frames as f64);
+        let outgoing = i16::from_le_bytes([sample[0], sample[1]]) as f64;
+        let incoming = head
+            .get(i * 2..i * 2 + 2)
+            .map_or(0.0, |bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f64);
+        let mixed = outgoing * (1.0 - fade_in) + incoming * fade_in;
+        sample.copy_from_slice(&(mixed.round() as i16).to_le_bytes());
+    }
+}
+
+fn spawn_visualization(ffplay_id: u32, visualization_data: Arc<Mutex<Vec<u8>>>) {
     thread::spawn(move || {
         let mut file = File::open("/dev/urandom").unwrap();
         while
@@ -44,7 +404,7 @@ pub fn stream_audio(
                 .unwrap()
                 .status.success()
         {
-            let mut data = visualization_data_clone.lock().unwrap();
+            let mut data = visualization_data.lock().unwrap();
             for v in data.iter_mut() {
                 let mut buf = [0u8; 1];
                 file.read_exact(&mut buf).unwrap();
@@ -53,5 +413,49 @@ pub fn stream_audio(
             thread::sleep(Duration::from_millis(100));
         }
     });
-    Ok(ffplay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|sample| sample.to_le_bytes()).collect()
+    }
+
+    fn samples(pcm: &[u8]) -> Vec<i16> {
+        pcm.chunks_exact(2).map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])).collect()
+    }
+
+    #[test]
+    fn crossfade_ramps_from_outgoing_to_incoming() {
+        let mut tail = pcm(&[1000, -1000, 1000, -1000, 1000, -1000, 1000, -1000]);
+        let head = pcm(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        crossfade(&mut tail, &head);
+        assert_eq!(samples(&tail), vec![1000, -1000, 750, -750, 500, -500, 250, -250]);
+    }
+
+    #[test]
+    fn crossfade_fades_both_channels_of_a_frame_alike() {
+        let mut tail = pcm(&[0, 0, 0, 0]);
+        let head = pcm(&[400, 800, 400, 800]);
+        crossfade(&mut tail, &head);
+        assert_eq!(samples(&tail), vec![0, 0, 200, 400]);
+    }
+
+    #[test]
+    fn crossfade_treats_a_short_head_as_silence() {
+        let mut tail = pcm(&[800, 800, 800, 800]);
+        let head = pcm(&[800, 800]);
+        crossfade(&mut tail, &head);
+        assert_eq!(samples(&tail), vec![800, 800, 400, 400]);
+    }
+
+    #[test]
+    fn crossfade_does_not_overflow_at_full_scale() {
+        let mut tail = pcm(&[i16::MAX, i16::MIN, i16::MAX, i16::MIN]);
+        let head = pcm(&[i16::MAX, i16::MIN, i16::MAX, i16::MIN]);
+        crossfade(&mut tail, &head);
+        assert_eq!(samples(&tail), vec![i16::MAX, i16::MIN, i16::MAX, i16::MIN]);
+    }
 }
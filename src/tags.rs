@@ -0,0 +1,186 @@
+// tags.rs
+use std::error::Error;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::process::{ Command, Stdio };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+const FFMPEG_PATH: &str = "ffmpeg";
+
+// Numbers temporary files, so writers working on the same file at once
+// each get their own
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub fn unique_suffix() -> String {
+    format!("{}-{}", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+// Hidden file next to `path` ffmpeg writes to before it replaces `path`
+fn tagging_path(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or("Invalid file name")?;
+    Ok(path.with_file_name(format!(".tagging-{}-{}", unique_suffix(), file_name)))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TrackMetadata {
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub year: Option<String>,
+    pub track: Option<u32>,
+    pub source_url: Option<String>,
+    pub cover_url: Option<String>,
+}
+
+impl TrackMetadata {
+    // Generic ffmpeg keys, mapped by each muxer to ID3, Vorbis comments or MP4 atoms
+    fn ffmpeg_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = vec![("title", self.title.clone())];
+        if let Some(artist) = &self.artist {
+            pairs.push(("artist", artist.clone()));
+        }
+        if let Some(album) = &self.album {
+            pairs.push(("album", album.clone()));
+        }
+        if let Some(year) = &self.year {
+            pairs.push(("date", year.clone()));
+        }
+        if let Some(track) = self.track {
+            pairs.push(("track", track.to_string()));
+        }
+        if let Some(url) = &self.source_url {
+            pairs.push(("comment", url.clone()));
+        }
+        pairs
+    }
+}
+
+// Containers ffmpeg can attach a cover picture to
+fn supports_cover(path: &Path) -> bool {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    matches!(ext.as_str(), "mp3" | "m4a" | "mp4" | "flac")
+}
+
+fn fetch_cover(url: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let client = reqwest::blocking::Client::new();
+    let bytes = client.get(url).send()?.error_for_status()?.bytes()?;
+    // One per download, batches and single downloads run side by side
+    let cover_path = std::env::temp_dir().join(format!("pjplayer-cover-{}", unique_suffix()));
+    fs::write(&cover_path, &bytes)?;
+    Ok(cover_path)
+}
+
+/// Rewrites the tags of `path` in place. When `metadata.cover_url` is set the
+/// image is fetched and embedded, otherwise any picture already in the file is kept.
+pub fn write_tags(path: &Path, metadata: &TrackMetadata) -> Result<(), Box<dyn Error>> {
+    let temp_path = tagging_path(path)?;
+
+    // A missing cover should not cost us the text tags
+    let cover = match &metadata.cover_url {
+        Some(url) if supports_cover(path) => fetch_cover(url).ok(),
+        _ => None,
+    };
+
+    let mut command = Command::new(FFMPEG_PATH);
+    command.args(["-y", "-loglevel", "error", "-i"]).arg(path);
+    match &cover {
+        Some(cover_path) => {
+            command
+                .arg("-i")
+                .arg(cover_path)
+                .args(["-map", "0:a", "-map", "1:v", "-c:a", "copy", "-c:v", "mjpeg"])
+                .args(["-disposition:v", "attached_pic"]);
+        }
+        None => {
+            command.args(["-map", "0", "-c", "copy"]);
+        }
+    }
+    for (key, value) in metadata.ffmpeg_pairs() {
+        command.arg("-metadata").arg(format!("{}={}", key, value));
+    }
+    let status = command
+        .args(["-id3v2_version", "3"])
+        .arg(&temp_path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+
+    if let Some(cover_path) = cover {
+        let _ = fs::remove_file(cover_path);
+    }
+
+    match status {
+        Ok(status) if status.success() => {
+            fs::rename(&temp_path, path)?;
+            Ok(())
+        }
+        Ok(status) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(format!("ffmpeg returned an error: Exit code {}", status).into())
+        }
+        Err(err) => Err(format!("Error executing ffmpeg: {}", err).into()),
+    }
+}
+
+/// Writes ReplayGain track (and album) gain and peak tags into `path`,
+/// leaving everything else in the file as it is
+pub fn write_replaygain_tags(
+    path: &Path,
+    track_gain: f64,
+    track_peak: f64,
+    album: Option<(f64, f64)>
+) -> Result<(), Box<dyn Error>> {
+    let temp_path = tagging_path(path)?;
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    let mut pairs = vec![
+        ("REPLAYGAIN_TRACK_GAIN", format!("{:.2} dB", track_gain)),
+        ("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", track_peak))
+    ];
+    if let Some((album_gain, album_peak)) = album {
+        pairs.push(("REPLAYGAIN_ALBUM_GAIN", format!("{:.2} dB", album_gain)));
+        pairs.push(("REPLAYGAIN_ALBUM_PEAK", format!("{:.6}", album_peak)));
+    }
+
+    let mut command = Command::new(FFMPEG_PATH);
+    command.args(["-y", "-loglevel", "error", "-i"]).arg(path).args(["-map", "0", "-c", "copy"]);
+    // Ogg keeps its comments on the audio stream
+    let metadata_flag = if matches!(ext.as_str(), "ogg" | "oga" | "opus") { "-metadata:s:a:0" } else { "-metadata" };
+    for (key, value) in pairs {
+        command.arg(metadata_flag).arg(format!("{}={}", key, value));
+    }
+    match ext.as_str() {
+        "mp3" => {
+            command.args(["-id3v2_version", "3"]);
+        }
+        // MP4 drops tags it has no atom for unless told otherwise
+        "m4a" | "mp4" => {
+            command.args(["-movflags", "use_metadata_tags"]);
+        }
+        _ => {}
+    }
+    let status = command.arg(&temp_path).stdout(Stdio::null()).stderr(Stdio::null()).status();
+
+    match status {
+        Ok(status) if status.success() => {
+            fs::rename(&temp_path, path)?;
+            Ok(())
+        }
+        Ok(status) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(format!("ffmpeg returned an error: Exit code {}", status).into())
+        }
+        Err(err) => Err(format!("Error executing ffmpeg: {}", err).into()),
+    }
+}
@@ -1,5 +1,7 @@
 use ratatui::{ prelude::*, widgets::*, layout::{ Layout, Direction, Constraint } };
-use crate::app::{ AppUi, View };
+use crate::app::{ AppUi, BrowseEntries, View };
+use crate::library::Facet;
+use crate::sources::RADIO;
 
 pub fn render(app: &AppUi, frame: &mut Frame) {
     let chunks = Layout::default()
@@ -27,9 +29,15 @@ pub fn render(app: &AppUi, frame: &mut Frame) {
         .alignment(Alignment::Center);
     frame.render_widget(header_paragraph, chunks[0]);
 
-    let second_header_paragraph = Paragraph::new("Made with 🌿 by Pocket Jack")
-        .style(white_style)
-        .alignment(Alignment::Center);
+    // Notices take the place of the tagline until the next key press
+    let second_header_paragraph = match &app.notice {
+        Some(notice) => Paragraph::new(notice.as_str()).style(Style::default().fg(Color::Yellow)),
+        None if app.is_offline() =>
+            Paragraph::new("OFFLINE - library, downloads and cached tracks only").style(
+                Style::default().fg(Color::LightRed)
+            ),
+        None => Paragraph::new("Made with 🌿 by Pocket Jack").style(white_style),
+    }.alignment(Alignment::Center);
     frame.render_widget(second_header_paragraph, chunks[1]);
 
     match app.current_view {
@@ -51,7 +59,7 @@ pub fn render(app: &AppUi, frame: &mut Frame) {
             frame.render_widget(input, search_chunks[0]);
         }
         View::InitialSelection => {
-            let buttons = vec!["1. STREAM", "2. DOWNLOAD"];
+            let buttons = ["1. STREAM", "2. DOWNLOAD", "3. BROWSE LIBRARY", "4. PLAYLISTS", "5. PODCASTS", "6. AUDIO CACHE"];
             let items: Vec<ListItem> = buttons
                 .iter()
                 .enumerate()
@@ -65,24 +73,33 @@ pub fn render(app: &AppUi, frame: &mut Frame) {
                 })
                 .collect();
 
+            let title = if app.offline {
+                "Select Mode (O to go online)"
+            } else {
+                "Select Mode (O for offline mode)"
+            };
             let list = List::new(items).block(
-                Block::default().borders(Borders::ALL).title("Select Mode").style(light_green_style)
+                Block::default().borders(Borders::ALL).title(title).style(light_green_style)
             );
 
             frame.render_widget(list, chunks[2]);
         }
         View::SourceSelection => {
-            let sources = vec!["1. YouTube", "2. Internet Archive"];
+            let sources = app.available_sources();
             let items: Vec<ListItem> = sources
                 .iter()
                 .enumerate()
-                .map(|(i, &source)| {
+                .map(|(i, source)| {
+                    let unavailable = app.is_offline() && !source.works_offline();
                     let style = if i == app.selected_source_index {
                         Style::default().bg(Color::Blue).fg(Color::White)
+                    } else if unavailable {
+                        dim_style
                     } else {
                         white_style
                     };
-                    ListItem::new(source).style(style)
+                    let note = if unavailable { " (unavailable offline)" } else { "" };
+                    ListItem::new(format!("{}. {}{}", i + 1, source.label(), note)).style(style)
                 })
                 .collect();
 
@@ -111,6 +128,7 @@ pub fn render(app: &AppUi, frame: &mut Frame) {
                 );
                 frame.render_widget(no_results_list, chunks[2]);
             } else {
+                let favorites = app.radio_favorites.lock().unwrap();
                 let results: Vec<ListItem> = app.search_results
                     .iter()
                     .enumerate()
@@ -121,25 +139,46 @@ pub fn render(app: &AppUi, frame: &mut Frame) {
                             white_style
                         };
 
-                        let content = Line::from(
-                            vec![
-                                Span::raw(format!("{}: ", i + 1)),
-                                Span::raw(&result.title),
-                                Span::raw(format!(" ({:?})", result.source))
-                            ]
-                        );
+                        let mut spans = vec![Span::raw(format!("{}: ", i + 1))];
+                        if app.playlist_title.is_some() {
+                            let mark = if app.marked_results.contains(&i) { "[x] " } else { "[ ] " };
+                            spans.insert(0, Span::raw(mark));
+                        }
+                        spans.extend([
+                            Span::raw(&result.title),
+                            Span::raw(format!(" ({})", app.sources.label(&result.source))),
+                        ]);
+                        if let Some(duration) = result.duration {
+                            spans.push(Span::styled(format!(" {}", format_duration(duration)), dim_style));
+                        }
+                        if app.is_downloaded(result) {
+                            spans.push(Span::styled(" [downloaded]", light_green_style));
+                        }
+                        if result.source == RADIO && favorites.contains(result) {
+                            spans.push(Span::styled(" [favorite]", light_green_style));
+                        }
+                        let content = Line::from(spans);
                         ListItem::new(content).style(style)
                     })
                     .collect();
 
+                let title = match &app.playlist_title {
+                    Some(playlist) =>
+                        format!(
+                            "{} ({} selected - SPACE to mark, A to mark all)",
+                            playlist,
+                            app.marked_results.len()
+                        ),
+                    None if app.source.key() == RADIO => "Stations (F to add or remove favorites)".to_string(),
+                    None => "Search Results".to_string(),
+                };
                 let list = List::new(results).block(
-                    Block::default()
-                        .borders(Borders::ALL)
-                        .title("Search Results")
-                        .style(light_green_style)
+                    Block::default().borders(Borders::ALL).title(title).style(light_green_style)
                 );
 
-                frame.render_widget(list, chunks[2]);
+                // Playlists can be long, keep the selected entry in view
+                let mut list_state = ListState::default().with_selected(app.selected_result_index);
+                frame.render_stateful_widget(list, chunks[2], &mut list_state);
             }
         }
         View::Streaming => {
@@ -148,22 +187,47 @@ pub fn render(app: &AppUi, frame: &mut Frame) {
                 .constraints([
                     Constraint::Percentage(20), // Song title
                     Constraint::Percentage(60), // Equalizer
-                    Constraint::Length(7), // Help texts (increased for 3 lines)
+                    Constraint::Length(8), // Help texts
                 ])
                 .split(chunks[2]);
 
+            let recording = if app.is_recording() { " ● REC" } else { "" };
             let song_block = Block::default()
                 .borders(Borders::ALL)
-                .title(if app.paused { "Now Paused" } else { "Now Streaming" })
+                .title(format!("{}{}", if app.paused { "Now Paused" } else { "Now Streaming" }, recording))
                 .style(light_green_style);
 
-            let song_name = if let Some(index) = app.selected_result_index {
-                &app.search_results[index].title
-            } else {
-                "Unknown Song"
+            let song_name = match &app.now_playing {
+                Some(result) => result.title.as_str(),
+                None => "Unknown Song",
+            };
+            let position = format_duration(app.position());
+            let progress = match app.now_playing.as_ref().and_then(|result| result.duration) {
+                Some(duration) => format!("{} / {}", position, format_duration(duration)),
+                None => position,
             };
+            let mut song_lines = vec![Line::from(song_name), Line::from(progress)];
+            // Radio stations announce the song they are playing
+            if let Some(title) = app.stream_title.lock().unwrap().as_ref() {
+                song_lines.push(Line::from(format!("♪ {}", title)));
+            }
+            // What the spoken-word filters have done so far
+            let report = app.playback_report.lock().unwrap();
+            let mut applied = Vec::new();
+            if app.effects.smart_speed && report.silence_saved >= 1.0 {
+                applied.push(format!("Smart speed saved {}", format_duration(report.silence_saved as u64)));
+            }
+            if let Some(gain) = app.effects.gain {
+                applied.push(format!("ReplayGain ({}) {:+.1} dB", app.gain_mode.label(), gain));
+            }
+            if let (true, Some(loudness), Some(gain)) = (app.effects.normalize, report.loudness, report.gain()) {
+                applied.push(format!("Loudness {:.1} LUFS, normalized by {:+.1} dB", loudness, gain));
+            }
+            if !applied.is_empty() {
+                song_lines.push(Line::from(applied.join(" · ")).style(dim_style));
+            }
 
-            let song_info = Paragraph::new(song_name)
+            let song_info = Paragraph::new(song_lines)
                 .style(white_style)
                 .block(song_block)
                 .alignment(Alignment::Center);
@@ -224,16 +288,39 @@ pub fn render(app: &AppUi, frame: &mut Frame) {
                 .style(light_green_style);
 
             let status_text = if app.paused {
-                "Paused - Press SPACE to play"
+                format!("Paused at {}x - Press SPACE to play, [ or ] to change speed", app.effects.speed)
             } else {
-                "Playing - Press SPACE to pause"
+                format!("Playing at {}x - Press SPACE to pause, [ or ] to change speed", app.effects.speed)
+            };
+            let on_off = |on: bool| if on { "on" } else { "off" };
+            let effects_text = format!(
+                "S smart speed ({})  L loudness normalization ({})  G ReplayGain ({})",
+                on_off(app.effects.smart_speed),
+                on_off(app.effects.normalize),
+                app.gain_mode.label()
+            );
+
+            let is_radio = app.now_playing.as_ref().is_some_and(|result| result.source == RADIO);
+            let queue_text = match app.queue.front() {
+                Some(next) => format!("Up next: {} ({} queued) - Press N to skip", next.title, app.queue.len()),
+                None => "Queue is empty".to_string(),
             };
 
             let help_text = Text::from(
                 vec![
                     Line::from(Span::raw(status_text)),
                     Line::from(Span::raw("Press 1-6 to change equalizer style")),
-                    Line::from(Span::raw("Press ← to go back to search results"))
+                    Line::from(Span::raw(effects_text)),
+                    Line::from(
+                        Span::raw(
+                            if is_radio {
+                                "Press F to add or remove the station from favorites, R to record song by song, ← to go back"
+                            } else {
+                                "Press R to save the track to the download folder, ← to go back"
+                            }
+                        )
+                    ),
+                    Line::from(Span::raw(queue_text))
                 ]
             );
 
@@ -244,6 +331,338 @@ pub fn render(app: &AppUi, frame: &mut Frame) {
 
             frame.render_widget(help_paragraph, streaming_chunks[2]);
         }
+        View::DownloadOptions => {
+            let options = &app.download_options;
+            let quality_label = if options.format.has_quality() {
+                options.quality.label()
+            } else {
+                "n/a"
+            };
+            let rows = [
+                format!("Format:  {}", options.format.label()),
+                format!("Quality: {}", quality_label),
+                "Start Download".to_string(),
+            ];
+            let items: Vec<ListItem> = rows
+                .iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    let style = if i == app.selected_option_index {
+                        Style::default().bg(Color::Blue).fg(Color::White)
+                    } else {
+                        white_style
+                    };
+                    ListItem::new(row.as_str()).style(style)
+                })
+                .collect();
+
+            let options_chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(5), Constraint::Length(3)])
+                .split(chunks[2]);
+
+            let list = List::new(items).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Download Options")
+                    .style(light_green_style)
+            );
+            frame.render_widget(list, options_chunks[0]);
+
+            let help_paragraph = Paragraph::new("Press ENTER to change a value or start, ← to go back")
+                .style(dim_style)
+                .block(Block::default().borders(Borders::ALL).style(light_green_style))
+                .alignment(Alignment::Center);
+            frame.render_widget(help_paragraph, options_chunks[1]);
+        }
+        View::DownloadExists => {
+            let path = app.selected_result_index
+                .and_then(|index| app.search_results.get(index))
+                .and_then(|result| {
+                    app.history
+                        .lock()
+                        .unwrap()
+                        .find(&result.source, &result.identifier)
+                        .map(|entry| entry.path.display().to_string())
+                })
+                .unwrap_or_default();
+
+            let rows = ["Open existing file", "Download again", "Cancel"];
+            let items: Vec<ListItem> = rows
+                .iter()
+                .enumerate()
+                .map(|(i, &row)| {
+                    let style = if i == app.selected_option_index {
+                        Style::default().bg(Color::Blue).fg(Color::White)
+                    } else {
+                        white_style
+                    };
+                    ListItem::new(row).style(style)
+                })
+                .collect();
+
+            let exists_chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(3), Constraint::Min(5)])
+                .split(chunks[2]);
+
+            let path_paragraph = Paragraph::new(path)
+                .style(dim_style)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title("Already Downloaded")
+                        .style(light_green_style)
+                )
+                .alignment(Alignment::Center);
+            frame.render_widget(path_paragraph, exists_chunks[0]);
+
+            let list = List::new(items).block(
+                Block::default().borders(Borders::ALL).title("What now?").style(light_green_style)
+            );
+            frame.render_widget(list, exists_chunks[1]);
+        }
+        View::Library => {
+            let entries = app.browse_entries();
+            let (title, rows, listing_tracks): (String, Vec<String>, bool) = match &entries {
+                BrowseEntries::Facets =>
+                    (
+                        "Library".to_string(),
+                        Facet::ALL.iter()
+                            .map(|facet| facet.label().to_string())
+                            .collect(),
+                        false,
+                    ),
+                BrowseEntries::Values(facet, values) =>
+                    (
+                        facet.label().to_string(),
+                        values
+                            .iter()
+                            .map(|(value, count)| format!("{} ({})", value, count))
+                            .collect(),
+                        false,
+                    ),
+                BrowseEntries::Tracks(tracks) =>
+                    (
+                        "Tracks".to_string(),
+                        tracks
+                            .iter()
+                            .map(|track| {
+                                let duration = track.duration.map(format_duration).unwrap_or_default();
+                                match &track.artist {
+                                    Some(artist) => format!("{} - {} {}", artist, track.title, duration),
+                                    None => format!("{} {}", track.title, duration),
+                                }
+                            })
+                            .collect(),
+                        true,
+                    ),
+            };
+
+            // Breadcrumb of the values picked so far
+            let path: Vec<&str> = app.browse_filters
+                .iter()
+                .map(|(_, value)| value.as_str())
+                .collect();
+            let block_title = if path.is_empty() {
+                title
+            } else {
+                format!("{} › {}", path.join(" › "), title)
+            };
+            let block_title = match entries {
+                BrowseEntries::Facets => block_title,
+                _ => format!("{} (sorted by {})", block_title, app.browse_sort.label(listing_tracks)),
+            };
+
+            let items: Vec<ListItem> = rows
+                .iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    let style = if i == app.browse_index {
+                        Style::default().bg(Color::Blue).fg(Color::White)
+                    } else {
+                        white_style
+                    };
+                    ListItem::new(row.as_str()).style(style)
+                })
+                .collect();
+
+            let library_chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(5), Constraint::Length(3)])
+                .split(chunks[2]);
+
+            let list = if items.is_empty() {
+                List::new(vec![ListItem::new("NOTHING IN THE LIBRARY YET =(").style(white_style)])
+            } else {
+                List::new(items)
+            };
+            let list = list.block(
+                Block::default().borders(Borders::ALL).title(block_title).style(light_green_style)
+            );
+            let mut list_state = ListState::default().with_selected(Some(app.browse_index));
+            frame.render_stateful_widget(list, library_chunks[0], &mut list_state);
+
+            let help_paragraph = Paragraph::new(
+                "ENTER open/play  P play all  A add to queue  S sort  ← back"
+            )
+                .style(dim_style)
+                .block(Block::default().borders(Borders::ALL).style(light_green_style))
+                .alignment(Alignment::Center);
+            frame.render_widget(help_paragraph, library_chunks[1]);
+        }
+        View::Playlists => {
+            let rows: Vec<String> = app.playlists.playlists
+                .iter()
+                .enumerate()
+                .map(|(i, playlist)| {
+                    // "+" adds to the playlist marked with a star
+                    let active = if Some(i) == app.active_playlist { " *" } else { "" };
+                    format!("{} ({} tracks){}", playlist.name, playlist.tracks.len(), active)
+                })
+                .collect();
+            render_menu(
+                frame,
+                chunks[2],
+                "Playlists",
+                &rows,
+                app.playlist_index,
+                "ENTER open  P play  N new  I import  E export  D delete  ← back"
+            );
+        }
+        View::PlaylistTracks => {
+            let (title, rows) = match app.playlists.playlists.get(app.playlist_index) {
+                Some(playlist) =>
+                    (
+                        playlist.name.clone(),
+                        playlist.tracks
+                            .iter()
+                            .enumerate()
+                            .map(|(i, track)| {
+                                let duration = track.duration.map(format_duration).unwrap_or_default();
+                                format!("{}: {} ({}) {}", i + 1, track.title, app.sources.label(&track.source), duration)
+                            })
+                            .collect(),
+                    ),
+                None => ("Playlist".to_string(), Vec::new()),
+            };
+            render_menu(
+                frame,
+                chunks[2],
+                &title,
+                &rows,
+                app.playlist_track_index,
+                "ENTER play from here  P play all  D remove  SHIFT+K/J move  ← back"
+            );
+        }
+        View::Podcasts => {
+            let podcasts = app.podcasts.lock().unwrap();
+            let rows: Vec<String> = podcasts.podcasts
+                .iter()
+                .map(|podcast| {
+                    let speed = podcast.speed.map(|speed| format!(" {}x", speed)).unwrap_or_default();
+                    format!(
+                        "{} ({} episodes, {} new){}",
+                        podcast.title,
+                        podcast.episodes.len(),
+                        podcast.unplayed(),
+                        speed
+                    )
+                })
+                .collect();
+            render_menu(
+                frame,
+                chunks[2],
+                "Podcasts",
+                &rows,
+                app.podcast_index,
+                "ENTER episodes  N subscribe  R refresh  SHIFT+R refresh all  [ ] speed  U unsubscribe  ← back"
+            );
+        }
+        View::Cache => {
+            let megabytes = |bytes: u64| (bytes as f64) / (1024.0 * 1024.0);
+            let rows: Vec<String> = app.cache_entries
+                .iter()
+                .map(|entry| format!("{}  {:.1} MB", entry.title, megabytes(entry.size)))
+                .collect();
+            let used: u64 = app.cache_entries
+                .iter()
+                .map(|entry| entry.size)
+                .sum();
+            let title = if app.cache.enabled() {
+                format!(
+                    "Audio cache: {} tracks, {:.0} of {:.0} MB",
+                    rows.len(),
+                    megabytes(used),
+                    megabytes(app.cache.max_bytes)
+                )
+            } else {
+                "Audio cache (off)".to_string()
+            };
+            render_menu(frame, chunks[2], &title, &rows, app.cache_index, "D remove  C clear all  ← back");
+        }
+        View::PodcastEpisodes => {
+            let podcasts = app.podcasts.lock().unwrap();
+            let (title, rows) = match podcasts.podcasts.get(app.podcast_index) {
+                Some(podcast) =>
+                    (
+                        podcast.title.clone(),
+                        podcast.episodes
+                            .iter()
+                            .map(|episode| {
+                                // Unplayed episodes are marked with a dot
+                                let marker = if episode.played { "  " } else { "● " };
+                                let published = episode.published.as_deref().unwrap_or("");
+                                let duration = episode.duration.map(format_duration).unwrap_or_default();
+                                format!("{}{}  {} {}", marker, episode.title, published, duration)
+                            })
+                            .collect(),
+                    ),
+                None => ("Episodes".to_string(), Vec::new()),
+            };
+            render_menu(
+                frame,
+                chunks[2],
+                &title,
+                &rows,
+                app.episode_index,
+                "ENTER play  D download  M mark played/unplayed  + add to playlist  ← back"
+            );
+        }
+        View::Resume => {
+            let (title, rows) = match &app.pending_resume {
+                Some((track, position)) =>
+                    (
+                        track.title.clone(),
+                        vec![
+                            format!("Resume from {}", format_duration(*position)),
+                            "Start from the beginning".to_string()
+                        ],
+                    ),
+                None => (String::new(), Vec::new()),
+            };
+            render_menu(frame, chunks[2], &title, &rows, app.selected_option_index, "ENTER play  ← back");
+        }
+        View::Prompt => {
+            let (title, input) = match &app.prompt {
+                Some(prompt) => (prompt.kind.title(), prompt.input.as_str()),
+                None => ("", ""),
+            };
+            let prompt_chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(3), Constraint::Length(3), Constraint::Min(0)])
+                .split(chunks[2]);
+
+            let input_paragraph = Paragraph::new(format!("{}▏", input))
+                .style(white_style)
+                .block(Block::default().borders(Borders::ALL).title(title).style(light_green_style));
+            frame.render_widget(input_paragraph, prompt_chunks[0]);
+
+            let help_paragraph = Paragraph::new("Press ENTER to confirm, ← to cancel")
+                .style(dim_style)
+                .alignment(Alignment::Center);
+            frame.render_widget(help_paragraph, prompt_chunks[1]);
+        }
         View::Downloading => {
             let download_status = app.download_status.lock().unwrap();
             let status_message = download_status.as_deref().unwrap_or("No downloads in progress");
@@ -262,3 +681,59 @@ pub fn render(app: &AppUi, frame: &mut Frame) {
         }
     }
 }
+
+pub fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, (seconds / 60) % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+// List with a highlighted row and a one-line help box underneath
+fn render_menu(
+    frame: &mut Frame,
+    area: Rect,
+    title: &str,
+    rows: &[String],
+    selected: usize,
+    help: &str
+) {
+    let light_green_style = Style::default().fg(Color::LightGreen);
+    let white_style = Style::default().fg(Color::White);
+    let dim_style = Style::default().fg(Color::Gray);
+
+    let menu_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(5), Constraint::Length(3)])
+        .split(area);
+
+    let items: Vec<ListItem> = if rows.is_empty() {
+        vec![ListItem::new("NOTHING HERE YET =(").style(white_style)]
+    } else {
+        rows.iter()
+            .enumerate()
+            .map(|(i, row)| {
+                let style = if i == selected {
+                    Style::default().bg(Color::Blue).fg(Color::White)
+                } else {
+                    white_style
+                };
+                ListItem::new(row.as_str()).style(style)
+            })
+            .collect()
+    };
+
+    let list = List::new(items).block(
+        Block::default().borders(Borders::ALL).title(title.to_string()).style(light_green_style)
+    );
+    let mut list_state = ListState::default().with_selected(Some(selected));
+    frame.render_stateful_widget(list, menu_chunks[0], &mut list_state);
+
+    let help_paragraph = Paragraph::new(help)
+        .style(dim_style)
+        .block(Block::default().borders(Borders::ALL).style(light_green_style))
+        .alignment(Alignment::Center);
+    frame.render_widget(help_paragraph, menu_chunks[1]);
+}
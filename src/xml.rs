@@ -0,0 +1,178 @@
+// xml.rs
+// Just enough XML for playlist and feed files: finding elements by name,
+// reading their text and attributes, and escaping text we write out.
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn unescape(text: &str) -> String {
+    let text = text.trim();
+    // CDATA sections are taken literally
+    if let Some(inner) = text.strip_prefix("<![CDATA[").and_then(|t| t.strip_suffix("]]>")) {
+        return inner.to_string();
+    }
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find(';') else {
+            output.push_str(&rest[start..]);
+            return output;
+        };
+        let entity = &rest[start + 1..start + end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(char::from_u32),
+            _ => None,
+        };
+        match decoded {
+            Some(c) => output.push(c),
+            None => output.push_str(&rest[start..start + end + 1]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    output.push_str(rest);
+    output
+}
+
+// Finds the next `<name` that is really that element and not a longer name
+// sharing the prefix (`<title` vs `<titles`)
+fn find_open_tag(xml: &str, name: &str) -> Option<usize> {
+    let pattern = format!("<{}", name);
+    let mut offset = 0;
+    while let Some(found) = xml[offset..].find(&pattern) {
+        let start = offset + found;
+        let next = xml[start + pattern.len()..].chars().next();
+        if matches!(next, Some('>') | Some('/') | Some(' ') | Some('\t') | Some('\n') | Some('\r')) {
+            return Some(start);
+        }
+        offset = start + pattern.len();
+    }
+    None
+}
+
+/// Every `<name ...>...</name>` element in `xml`, as (attributes, inner text)
+/// pairs. Self-closing elements come back with empty inner text.
+pub fn elements<'a>(xml: &'a str, name: &str) -> Vec<(&'a str, &'a str)> {
+    let close = format!("</{}>", name);
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = find_open_tag(rest, name) {
+        let after_name = &rest[start + name.len() + 1..];
+        let Some(tag_end) = after_name.find('>') else {
+            break;
+        };
+        let attributes = &after_name[..tag_end];
+        if let Some(attributes) = attributes.strip_suffix('/') {
+            found.push((attributes, ""));
+            rest = &after_name[tag_end + 1..];
+            continue;
+        }
+        let body = &after_name[tag_end + 1..];
+        let Some(body_end) = body.find(&close) else {
+            break;
+        };
+        found.push((attributes, &body[..body_end]));
+        rest = &body[body_end + close.len()..];
+    }
+    found
+}
+
+/// Unescaped text of the first `<name>` element
+pub fn text(xml: &str, name: &str) -> Option<String> {
+    elements(xml, name)
+        .first()
+        .map(|(_, body)| unescape(body))
+        .filter(|text| !text.is_empty())
+}
+
+/// Unescaped value of the `name` attribute in the attributes part of a tag,
+/// as returned by `elements`
+pub fn attribute(attributes: &str, name: &str) -> Option<String> {
+    let mut rest = attributes;
+    while let Some(found) = rest.find(name) {
+        let before = rest[..found].chars().last();
+        let after = rest[found + name.len()..].trim_start();
+        // Skip longer names that end in `name` (`href` in `hreflang`, say)
+        if before.is_none_or(char::is_whitespace) {
+            if let Some(value) = after.strip_prefix('=') {
+                let value = value.trim_start();
+                let quote = value.chars().next()?;
+                if quote == '"' || quote == '\'' {
+                    let end = value[1..].find(quote)?;
+                    return Some(unescape(&value[1..end + 1]));
+                }
+            }
+        }
+        rest = &rest[found + name.len()..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_elements_by_exact_name() {
+        let xml = "<titles><title>One</title></titles><title lang=\"en\">Two</title>";
+        assert_eq!(elements(xml, "title"), vec![("", "One"), (" lang=\"en\"", "Two")]);
+        assert_eq!(elements(xml, "titles"), vec![("", "<title>One</title>")]);
+        assert!(elements(xml, "tit").is_empty());
+    }
+
+    #[test]
+    fn returns_self_closing_elements_with_no_body() {
+        let xml = "<enclosure url=\"a.mp3\"/><enclosure url=\"b.mp3\" /><enclosure url=\"c.mp3\"></enclosure>";
+        let found = elements(xml, "enclosure");
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0], (" url=\"a.mp3\"", ""));
+        assert_eq!(found[1], (" url=\"b.mp3\" ", ""));
+        assert_eq!(found[2], (" url=\"c.mp3\"", ""));
+    }
+
+    #[test]
+    fn stops_at_unterminated_elements() {
+        assert_eq!(elements("<a>one</a><a>two", "a"), vec![("", "one")]);
+        assert!(elements("<a", "a").is_empty());
+    }
+
+    #[test]
+    fn reads_text() {
+        assert_eq!(text("<title> Rock &amp; Roll </title>", "title").as_deref(), Some("Rock & Roll"));
+        assert_eq!(text("<title><![CDATA[a < b & c]]></title>", "title").as_deref(), Some("a < b & c"));
+        assert_eq!(text("<title></title>", "title"), None);
+        assert_eq!(text("<name>x</name>", "title"), None);
+    }
+
+    #[test]
+    fn reads_attributes() {
+        let attributes = " rel=\"enclosure\" hreflang=\"en\" href='https://example.com/?a=1&amp;b=2'";
+        assert_eq!(attribute(attributes, "href").as_deref(), Some("https://example.com/?a=1&b=2"));
+        assert_eq!(attribute(attributes, "hreflang").as_deref(), Some("en"));
+        assert_eq!(attribute(attributes, "rel").as_deref(), Some("enclosure"));
+        assert_eq!(attribute(attributes, "lang"), None);
+        assert_eq!(attribute(attributes, "type"), None);
+        assert_eq!(attribute("url = \"spaced\"", "url").as_deref(), Some("spaced"));
+        assert_eq!(attribute("xlink:href=\"x\"", "href"), None);
+        assert_eq!(attribute("url=unquoted", "url"), None);
+    }
+
+    #[test]
+    fn round_trips_escaped_text() {
+        let original = "<Tom & Jerry's \"Show\">";
+        assert_eq!(escape(original), "&lt;Tom &amp; Jerry&apos;s &quot;Show&quot;&gt;");
+        assert_eq!(unescape(&escape(original)), original);
+        assert_eq!(unescape("&#65;&#x42;&unknown; & done"), "AB&unknown; & done");
+    }
+}